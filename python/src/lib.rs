@@ -77,11 +77,11 @@ impl Game {
     }).unwrap_or(Err(exc::Exception::new("unexpected panic")))
   }
 
-  fn execute(&mut self, m: &mut Move) -> PyResult<()> {
+  fn execute(&mut self, m: &Move) -> PyResult<()> {
     panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
       match self.game.validate(&m.m) {
         rustak::game::MoveValidity::Valid => {
-          self.game.execute(&mut m.m);
+          self.game.execute(&m.m);
           Ok(())
         },
         val => Err(exc::Exception::new(format!("{:?}", val)))
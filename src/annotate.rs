@@ -0,0 +1,150 @@
+// Automatic annotation of played games. Each position is searched to a fixed
+// depth, and moves that score much worse than the engine's choice are marked
+// as blunders or questionable, with the engine's move as a comment. Moves
+// that make a road threat are marked as tak, or tinue if the solver can prove
+// the win.
+use engine::{self, Engine};
+use eval::{Evaluator, Handcrafted};
+use game::{MoveValidity, Winner};
+use ptn::{self, Ptn, SubjAnnotation, TakAnnotation};
+use tinue::{self, TinueResult};
+
+#[derive(Debug,Clone,Copy)]
+pub struct Options {
+  // Search depth for each position
+  pub depth: u32,
+  // How much worse than the best move, in evaluation units, a move has to
+  // score to be marked
+  pub blunder: i32,
+  pub questionable: i32,
+  // Search limit for the tinue solver, run after each tak
+  pub tinue_nodes: usize,
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Options {
+      depth: 3,
+      blunder: 300,
+      questionable: 100,
+      tinue_nodes: 100_000,
+    }
+  }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum AnnotateError {
+  // The tags don't give a valid start position
+  InvalidStart,
+  // The move at this ply can't be played
+  IllegalMove(usize, MoveValidity),
+  // The game was over before the move at this ply
+  MoveAfterEnd(usize),
+}
+
+// Annotate with the built in evaluation
+pub fn annotate(ptn: &Ptn, options: &Options) -> Result<Ptn, AnnotateError> {
+  annotate_with(ptn, &mut Engine::new(Handcrafted::default()), options)
+}
+
+// Copy of ptn with each move's tak and subjective annotations filled in. Moves
+// that aren't marked keep any subjective annotation they had, and existing
+// comments are kept.
+pub fn annotate_with<E: Evaluator>(ptn: &Ptn, engine: &mut Engine<E>, options: &Options) -> Result<Ptn, AnnotateError> {
+  let mut g = ptn.start().ok_or(AnnotateError::InvalidStart)?;
+  let mut out = ptn.clone();
+  let depth = options.depth.max(1);
+  let limits = |depth| engine::Limits { depth: Some(depth), ..engine::Limits::default() };
+  engine.clear();
+
+  for (ply, annotated) in out.moves.iter_mut().enumerate() {
+    let m = annotated.m;
+    let player = g.cur_player();
+    if g.status().is_some() {
+      return Err(AnnotateError::MoveAfterEnd(ply));
+    }
+    let best = engine.search(&g, limits(depth));
+    match g.validate(&m) {
+      MoveValidity::Valid => {},
+      v => return Err(AnnotateError::IllegalMove(ply, v)),
+    }
+    g.execute(&m);
+
+    // Score of the move for the player who made it, searched one ply less so
+    // it's comparable with the best move's score
+    let score = match g.status() {
+      Some(Winner::Draw) => 0,
+      Some(Winner::Road(p)) | Some(Winner::Flat(p)) | Some(Winner::Other(p)) => if p == player { engine::WIN } else { -engine::WIN },
+      None if depth == 1 => -engine.evaluator().evaluate(&g),
+      None => -engine.search(&g, limits(depth - 1)).score,
+    };
+
+    annotated.annotation.0 = if g.status().is_none() && g.is_tak() {
+      let limits = tinue::Limits { nodes: Some(options.tinue_nodes), time: None };
+      match tinue::solve_for(&g, player, limits) {
+        TinueResult::Tinue(_) => Some(TakAnnotation::Tinue),
+        _ => Some(TakAnnotation::Tak),
+      }
+    } else {
+      None
+    };
+
+    if let Some(best_move) = best.best.filter(|&b| b != m) {
+      let loss = best.score - score;
+      let mark = if loss >= options.blunder {
+        Some(SubjAnnotation::Blunder)
+      } else if loss >= options.questionable {
+        Some(SubjAnnotation::Questionable)
+      } else {
+        None
+      };
+      if mark.is_some() {
+        annotated.annotation.1 = mark;
+        annotated.comments.push(format!("best: {}", ptn::to_string(&best_move)));
+      }
+    }
+  }
+  Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+  use super::{annotate, AnnotateError, Options};
+  use game::MoveValidity;
+  use ptn::{self, SubjAnnotation, TakAnnotation};
+
+  #[test]
+  fn annotations() {
+    // White builds towards a road along the third rank and up the c file.
+    // Black lets c3 make threats on both, which is tinue.
+    let game = ptn::parse("[Size \"5\"]\n\n\
+      1. e1 a5 2. a3 a1 3. b3 b1 4. d3 b5 5. c1 d1 6. c2 e5 7. c4 d5 {hmm}\n\
+      8. c3 Se3 9. c5\nR-0\n").unwrap();
+    let annotated = annotate(&game, &Options::default()).unwrap();
+    let moves = &annotated.moves;
+    assert_eq!(moves.len(), 17);
+
+    assert_eq!(moves[13].comments[0], "hmm");
+    assert_eq!(moves[14].annotation, (Some(TakAnnotation::Tinue), None));
+    assert_eq!(moves[15].annotation.0, None);
+    assert_eq!(moves[16].annotation, (None, None));
+    assert!(annotated.to_string().contains("8. c3'' Se3\n"));
+
+    // One threat that black doesn't block
+    let game = ptn::parse("[Size \"5\"]\n\n1. e1 e5 2. a3 b1 3. b3 c1 4. c3 a5 5. d3 b5 6. e3\n").unwrap();
+    let annotated = annotate(&game, &Options::default()).unwrap();
+    assert_eq!(annotated.moves[8].annotation.0, Some(TakAnnotation::Tak));
+    assert_eq!(annotated.moves[9].annotation.1, Some(SubjAnnotation::Blunder));
+    assert!(annotated.to_string().contains("5. d3' b5?? {best: e3}\n"));
+  }
+
+  #[test]
+  fn errors() {
+    let game = ptn::parse("[Size \"5\"]\n\n1. a1 a1\n").unwrap();
+    assert_eq!(annotate(&game, &Options::default()).unwrap_err(), AnnotateError::IllegalMove(1, MoveValidity::SquareOccupied));
+    let game = ptn::parse("[Size \"4\"]\n\n1. d4 a1 2. a2 c4 3. a3 b4 4. a4 d1\n").unwrap();
+    assert_eq!(annotate(&game, &Options::default()).unwrap_err(), AnnotateError::MoveAfterEnd(7));
+    let game = ptn::parse("[Size \"5\"]\n[TPS \"x5/x5\"]\n\n1. a1\n").unwrap();
+    assert_eq!(annotate(&game, &Options::default()).unwrap_err(), AnnotateError::InvalidStart);
+  }
+}
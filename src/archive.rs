@@ -0,0 +1,303 @@
+// Reading the games archive published by playtak.com (games_anon.db), a sqlite
+// database with one row per game played on the server. Each game is replayed
+// as it's read, so games the server recorded wrongly can be told apart from
+// clean ones.
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sqlite;
+use game::{Game, Komi, Move, MoveValidity, Rules, Winner};
+use playtak;
+
+// Why a game in the archive can't be taken at face value
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Problem {
+  // The notation or result column couldn't be parsed
+  Unreadable,
+  // The move at this ply isn't legal
+  IllegalMove(usize),
+  // The game was already decided by the move at this ply, but more moves follow
+  MovesAfterEnd(usize),
+  // The result is a road or flat win, but the moves don't finish the game
+  Truncated,
+  // The moves finish the game with this result, which isn't the one recorded.
+  // Older games were scored without the dragon clause.
+  ResultMismatch(Winner),
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Record {
+  pub id: i64,
+  pub date: SystemTime,
+  pub white: String,
+  pub black: String,
+  pub size: usize,
+  // Piece counts are the standard ones for the size unless the game was
+  // played with others
+  pub komi: Komi,
+  pub flats: u8,
+  pub caps: u8,
+  pub moves: Vec<Move>,
+  // None for results the server recorded that aren't valid, i.e. "0-0"
+  pub result: Option<Winner>,
+  pub problem: Option<Problem>,
+}
+
+impl Record {
+  pub fn is_clean(&self) -> bool {
+    self.problem.is_none()
+  }
+
+  // Rules the game was played with, or None if the size isn't valid
+  pub fn rules(&self) -> Option<Rules> {
+    Some(Rules { komi: self.komi, flats: self.flats, caps: self.caps, ..Rules::new(self.size)? })
+  }
+}
+
+pub struct Archive {
+  connection: sqlite::Connection,
+}
+
+impl Archive {
+  pub fn open<P: AsRef<Path>>(path: P) -> sqlite::Result<Self> {
+    Ok(Archive { connection: sqlite::open(path)? })
+  }
+
+  // Every game, in the order they were played
+  pub fn games(&self) -> sqlite::Result<Games> {
+    self.games_where("1")
+  }
+
+  // Games matching a SQL condition on the games table, i.e.
+  //   archive.games_where("size = 5 AND player_white != 'sTAKbot1'")
+  pub fn games_where(&self, condition: &str) -> sqlite::Result<Games> {
+    // Older dumps don't have the komi and piece count columns, and all their
+    // games were played with the standard rules
+    let rules = if self.has_column("komi")? { "komi, pieces, capstones" } else { "0, -1, -1" };
+    let statement = self.connection.prepare(format!("
+      SELECT id, date, player_white, player_black, size, notation, result, {}
+      FROM games
+      WHERE {}
+      ORDER BY id
+    ", rules, condition))?;
+    Ok(Games { statement })
+  }
+
+  fn has_column(&self, name: &str) -> sqlite::Result<bool> {
+    let mut statement = self.connection.prepare("PRAGMA table_info(games)")?;
+    while statement.next()? == sqlite::State::Row {
+      if statement.read::<String>(1)? == name {
+        return Ok(true);
+      }
+    }
+    Ok(false)
+  }
+}
+
+// Iterator over the rows of a query, replaying each game
+pub struct Games<'a> {
+  statement: sqlite::Statement<'a>,
+}
+
+impl<'a> Games<'a> {
+  fn read(&self) -> sqlite::Result<Record> {
+    let id = self.statement.read::<i64>(0)?;
+    let millis = self.statement.read::<i64>(1)?;
+    let size = self.statement.read::<i64>(4)? as usize;
+    let notation = self.statement.read::<String>(5)?;
+    let result = self.statement.read::<String>(6)?;
+    // Piece counts of -1 mean the standard ones
+    let standard = Rules::new(size);
+    let count = |n: i64, standard: Option<u8>| if n < 0 { standard.unwrap_or(0) } else { n.min(255) as u8 };
+    let mut record = Record {
+      id,
+      date: UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64),
+      white: self.statement.read::<String>(2)?,
+      black: self.statement.read::<String>(3)?,
+      size,
+      komi: Komi::from_half_flats(self.statement.read::<i64>(7)?.clamp(0, 255) as u8),
+      flats: count(self.statement.read::<i64>(8)?, standard.map(|r| r.flats)),
+      caps: count(self.statement.read::<i64>(9)?, standard.map(|r| r.caps)),
+      moves: Vec::new(),
+      result: None,
+      problem: None,
+    };
+
+    let moves = playtak::parse_moves(&notation).ok()
+      .and_then(|(moves, rest)| if rest.trim().is_empty() { Some(moves) } else { None });
+    let result = playtak::parse_result(result.trim()).ok()
+      .and_then(|(result, rest)| if rest.is_empty() { Some(result) } else { None });
+    record.problem = match (moves.as_ref(), result, record.rules()) {
+      (Some(moves), Some(result), Some(rules)) => classify(rules, moves, result),
+      _ => Some(Problem::Unreadable),
+    };
+    record.moves = moves.unwrap_or_default();
+    record.result = result;
+    Ok(record)
+  }
+}
+
+impl<'a> Iterator for Games<'a> {
+  type Item = sqlite::Result<Record>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    match self.statement.next() {
+      Ok(sqlite::State::Row) => Some(self.read()),
+      Ok(sqlite::State::Done) => None,
+      Err(e) => Some(Err(e)),
+    }
+  }
+}
+
+// Replay a game and check it against its recorded result. Results that don't
+// come from the board (resignations, time, agreed draws) can't be checked
+// beyond the game not having ended some other way.
+pub fn classify(rules: Rules, moves: &[Move], result: Winner) -> Option<Problem> {
+  let mut g = match Game::with_rules(rules) {
+    Some(g) => g,
+    None => return Some(Problem::Unreadable),
+  };
+  for (ply, m) in moves.iter().enumerate() {
+    if g.status().is_some() {
+      return Some(Problem::MovesAfterEnd(ply - 1));
+    }
+    if g.validate(m) != MoveValidity::Valid {
+      return Some(Problem::IllegalMove(ply));
+    }
+    g.execute(m);
+  }
+  match (g.status(), result) {
+    (Some(status), _) if status != result => Some(Problem::ResultMismatch(status)),
+    (None, Winner::Road(_)) | (None, Winner::Flat(_)) => Some(Problem::Truncated),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::path::PathBuf;
+  use std::time::{Duration, UNIX_EPOCH};
+  use sqlite;
+  use game::{Komi, Player, Rules, Winner};
+  use playtak::parse_moves;
+  use super::{classify, Archive, Problem};
+
+  fn moves(notation: &str) -> Vec<::game::Move> {
+    parse_moves(notation).unwrap().0
+  }
+
+  #[test]
+  fn classify_games() {
+    let five = Rules::new(5).unwrap();
+    let road = "P A1,P E1,P E2,P A2,P E3,P A3,P E4,P A4";
+    let white = Winner::Road(Player::White);
+    assert_eq!(classify(five, &moves(road), white), Some(Problem::Truncated));
+    assert_eq!(classify(five, &moves(&format!("{},P E5", road)), white), None);
+    assert_eq!(classify(five, &moves(&format!("{},P E5", road)), Winner::Flat(Player::White)), Some(Problem::ResultMismatch(white)));
+    assert_eq!(classify(five, &moves(&format!("{},P E5,P B1", road)), white), Some(Problem::MovesAfterEnd(8)));
+    assert_eq!(classify(five, &moves("P A1,P A1"), white), Some(Problem::IllegalMove(1)));
+    assert_eq!(classify(five, &moves(road), Winner::Other(Player::Black)), None);
+    assert_eq!(classify(five, &moves(road), Winner::Draw), None);
+
+    // Filling the reserves decides the game on flats, with komi counted
+    let fill = moves("P A1,P C3,P A3,P C1,P B2");
+    let three = Rules { flats: 3, caps: 0, ..Rules::new(3).unwrap() };
+    assert_eq!(classify(three, &fill, Winner::Flat(Player::White)), None);
+    let komi = Rules { komi: Komi::from_half_flats(4), ..three };
+    assert_eq!(classify(komi, &fill, Winner::Flat(Player::Black)), None);
+    assert_eq!(classify(komi, &fill, Winner::Flat(Player::White)), Some(Problem::ResultMismatch(Winner::Flat(Player::Black))));
+  }
+
+  #[test]
+  fn read_games() {
+    let connection = sqlite::open(":memory:").unwrap();
+    connection.execute("
+      CREATE TABLE games (
+        id INTEGER PRIMARY KEY, date INT, size INT, player_white VARCHAR(20),
+        player_black VARCHAR(20), notation TEXT, result VARCHAR(10)
+      );
+      INSERT INTO games VALUES (1, 1461456000000, 5, 'alice', 'bob', 'P A1,P E1,P E2', '0-1');
+      INSERT INTO games VALUES (2, 1461456000000, 4, 'bob', 'alice', 'P A1,P A1', 'R-0');
+      INSERT INTO games VALUES (3, 1461456000000, 4, 'bob', 'alice', 'P A1,Q B2', '1-0');
+      INSERT INTO games VALUES (4, 1461456000000, 4, 'carol', 'alice', '', '0-0');
+    ").unwrap();
+    let archive = Archive { connection };
+
+    let games : Vec<_> = archive.games().unwrap().map(|g| g.unwrap()).collect();
+    assert_eq!(games.len(), 4);
+    assert_eq!((games[0].white.as_str(), games[0].black.as_str(), games[0].size), ("alice", "bob", 5));
+    assert_eq!(games[0].date, UNIX_EPOCH + Duration::from_secs(1_461_456_000));
+    assert_eq!(games[0].moves.len(), 3);
+    assert_eq!(games[0].result, Some(Winner::Other(Player::Black)));
+    assert!(games[0].is_clean());
+    assert_eq!(games[1].problem, Some(Problem::IllegalMove(1)));
+    assert_eq!(games[2].problem, Some(Problem::Unreadable));
+    assert_eq!((games[3].result, games[3].problem), (None, Some(Problem::Unreadable)));
+
+    let ids : Vec<i64> = archive.games_where("player_white = 'bob'").unwrap().map(|g| g.unwrap().id).collect();
+    assert_eq!(ids, vec![2, 3]);
+    assert_eq!(games[0].rules(), Rules::new(5));
+
+    // Newer dumps record komi and piece counts, with -1 for the standard count
+    let connection = sqlite::open(":memory:").unwrap();
+    connection.execute("
+      CREATE TABLE games (
+        id INTEGER PRIMARY KEY, date INT, size INT, player_white VARCHAR(20),
+        player_black VARCHAR(20), notation TEXT, result VARCHAR(10),
+        komi INT DEFAULT 0, pieces INT DEFAULT -1, capstones INT DEFAULT -1
+      );
+      INSERT INTO games VALUES (1, 1461456000000, 3, 'alice', 'bob', 'P A1,P C3,P A3,P C1,P B2', '0-F', 4, 3, 0);
+      INSERT INTO games VALUES (2, 1461456000000, 4, 'alice', 'bob', 'P A1,P D4,P B2 C', '0-1', 0, -1, 1);
+      INSERT INTO games VALUES (3, 1461456000000, 4, 'alice', 'bob', 'P A1,P D4,P B2 C', '0-1', 0, -1, -1);
+    ").unwrap();
+    let archive = Archive { connection };
+    let games : Vec<_> = archive.games().unwrap().map(|g| g.unwrap()).collect();
+    assert_eq!((games[0].komi, games[0].flats, games[0].caps), (Komi::from_half_flats(4), 3, 0));
+    assert!(games[0].is_clean());
+    assert_eq!((games[1].flats, games[1].caps), (15, 1));
+    assert!(games[1].is_clean());
+    assert_eq!(games[2].problem, Some(Problem::IllegalMove(2)));
+  }
+
+  // Games that have the wrong result recorded from when playtak did not properly implement the
+  // dragon rule
+  const PLAYTAK_DRAGON_RULE_BUG_GAMES : [i64; 13] = [3172,4932,6037,6249,14270,15070,15527,16082,16325,17091,17316,17405,17532];
+  // Games that have extra moves past when the game has been won, and the database has the wrong
+  // result recorded
+  const PLAYTAK_INCORRECT_RESULT_GAMES : [i64; 7] = [380, 3018, 9329, 15296, 54675, 81952, 116539];
+  //
+  const PLAYTAK_UNKNOWN_PROBLEM_GAMES: [i64; 3] = [9013,9449,9598];
+
+  // Replays the whole of a playtak dump, which has to be put in the crate
+  // root as games_anon.db.1. Run with --ignored.
+  #[test]
+  #[ignore]
+  fn playtak_archive() {
+    let mut dbfile = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    dbfile.push("games_anon.db.1");
+    assert!(dbfile.exists(), "{} is missing", dbfile.display());
+    let archive = Archive::open(dbfile).unwrap();
+    let mut clean = 0;
+    for game in archive.games().unwrap() {
+      let game = game.unwrap();
+      let known = PLAYTAK_DRAGON_RULE_BUG_GAMES.contains(&game.id)
+        || PLAYTAK_INCORRECT_RESULT_GAMES.contains(&game.id)
+        || PLAYTAK_UNKNOWN_PROBLEM_GAMES.contains(&game.id);
+      match (game.problem, game.result) {
+        (None, _) => clean += 1,
+        // Some games between sTAKbot1 and sTAKbot2 contain illegal moves
+        (Some(Problem::IllegalMove(_)), _) => assert!(game.white.starts_with("sTAKbot") && game.black.starts_with("sTAKbot"), "{:?}", game),
+        // Not sure why there exist games with a 0-0 result. Perhaps these were offered draws?
+        (Some(Problem::Unreadable), None) => {},
+        // Resignations and timeouts can't be checked against the moves
+        (Some(Problem::MovesAfterEnd(_)), Some(Winner::Other(_))) => {},
+        (Some(Problem::ResultMismatch(_)), Some(Winner::Other(_))) => {},
+        (Some(Problem::Truncated), Some(Winner::Other(_))) => {},
+        // Moves after the end are harmless if the game ended with the recorded result
+        (Some(Problem::MovesAfterEnd(ply)), Some(result)) =>
+          assert!(known || classify(game.rules().unwrap(), &game.moves[.. ply + 1], result).is_none(), "{:?}", game),
+        (Some(_), _) => assert!(known, "{:?}", game),
+      }
+    }
+    assert!(clean > 0);
+  }
+}
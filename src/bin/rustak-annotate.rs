@@ -0,0 +1,50 @@
+// Annotates PTN files with tak and tinue marks and the engine's opinion of
+// each move, and writes the annotated games to stdout.
+//
+// Usage: rustak-annotate [--depth N] PTN...
+
+extern crate rustak;
+
+use std::env;
+use std::fs;
+use std::process;
+use rustak::annotate::{self, Options};
+use rustak::ptn;
+
+fn usage() -> ! {
+  eprintln!("usage: rustak-annotate [--depth N] PTN...");
+  process::exit(2);
+}
+
+fn main() {
+  let mut options = Options::default();
+  let mut files = Vec::new();
+  let mut args = env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--depth" => options.depth = args.next().and_then(|d| d.parse().ok()).unwrap_or_else(|| usage()),
+      _ => files.push(arg),
+    }
+  }
+  if files.is_empty() {
+    usage();
+  }
+
+  let mut failed = false;
+  for file in files {
+    let annotated = fs::read_to_string(&file)
+      .map_err(|e| e.to_string())
+      .and_then(|text| ptn::parse(&text).map_err(|e| format!("{:?}", e)))
+      .and_then(|game| annotate::annotate(&game, &options).map_err(|e| format!("{:?}", e)));
+    match annotated {
+      Ok(game) => println!("{}", game.to_string()),
+      Err(e) => {
+        eprintln!("{}: {}", file, e);
+        failed = true;
+      },
+    }
+  }
+  if failed {
+    process::exit(1);
+  }
+}
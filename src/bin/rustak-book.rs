@@ -0,0 +1,93 @@
+// Builds an opening book for rustak-tei from PTN files and, with the sqlite
+// feature, a playtak games_anon.db archive.
+//
+// Usage: rustak-book OUT --size N [--plies N] [--db FILE] [PTN...]
+
+extern crate rustak;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::process;
+use rustak::book::Book;
+use rustak::ptn;
+
+fn usage() -> ! {
+  eprintln!("usage: rustak-book OUT --size N [--plies N] [--db FILE] [PTN...]");
+  process::exit(2);
+}
+
+fn main() {
+  let mut out = None;
+  let (mut size, mut plies) = (None, 16);
+  let mut db = None;
+  let mut files = Vec::new();
+  let mut args = env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--size" => size = args.next().and_then(|s| s.parse().ok()),
+      "--plies" => plies = args.next().and_then(|s| s.parse().ok()).unwrap_or_else(|| usage()),
+      "--db" => db = Some(args.next().unwrap_or_else(|| usage())),
+      _ if out.is_none() => out = Some(arg),
+      _ => files.push(arg),
+    }
+  }
+  let (out, size) = match (out, size) {
+    (Some(out), Some(size)) => (out, size),
+    _ => usage(),
+  };
+
+  let mut book = Book::new(size, plies);
+  let mut added = 0;
+  for file in files {
+    let text = match fs::read_to_string(&file) {
+      Ok(text) => text,
+      Err(e) => {
+        eprintln!("can't read {}: {}", file, e);
+        continue;
+      },
+    };
+    match ptn::parse(&text) {
+      Ok(game) => if book.add_ptn(&game) { added += 1 },
+      Err(e) => eprintln!("can't parse {}: {:?}", file, e),
+    }
+  }
+  if let Some(db) = db {
+    added += add_archive(&mut book, &db);
+  }
+
+  let written = File::create(&out).and_then(|f| {
+    let mut w = BufWriter::new(f);
+    book.write(&mut w)?;
+    w.flush()
+  });
+  if let Err(e) = written {
+    eprintln!("can't write {}: {}", out, e);
+    process::exit(1);
+  }
+  eprintln!("{} games, {} positions", added, book.len());
+}
+
+#[cfg(feature = "sqlite")]
+fn add_archive(book: &mut Book, db: &str) -> usize {
+  use rustak::archive::Archive;
+  let games = Archive::open(db).and_then(|archive| {
+    let mut added = 0;
+    for record in archive.games_where(&format!("size = {}", book.size()))? {
+      if book.add_record(&record?) {
+        added += 1;
+      }
+    }
+    Ok(added)
+  });
+  games.unwrap_or_else(|e| {
+    eprintln!("can't read {}: {:?}", db, e);
+    process::exit(1);
+  })
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn add_archive(_: &mut Book, _: &str) -> usize {
+  eprintln!("rustak-book was built without the sqlite feature");
+  process::exit(2);
+}
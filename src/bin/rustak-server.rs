@@ -0,0 +1,815 @@
+// Small playtak-compatible server, for bot matches on a LAN or in CI. It
+// speaks the same line protocol as playtak.com, checks every move, runs the
+// clocks, and writes finished games out as PTN files and/or to a sqlite
+// database with the same games table as playtak's games_anon.db.
+//
+// Usage: rustak-server [--port N] [--ptn DIR] [--db FILE]
+
+extern crate rustak;
+#[cfg(feature = "sqlite")]
+extern crate sqlite;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use rustak::game::{Game, Move, Player, Rules, Winner};
+use rustak::history::History;
+use rustak::playtak::{self, ClientMessage, Seek, ServerMessage};
+use rustak::ptn::Tag;
+
+// Index into per-player arrays, white first
+fn side(player: Player) -> usize {
+  match player {
+    Player::White => 0,
+    Player::Black => 1,
+  }
+}
+
+fn color(side: usize) -> Player {
+  if side == 0 { Player::White } else { Player::Black }
+}
+
+struct Conn {
+  // None until logged in
+  name: Option<String>,
+  game: Option<u32>,
+}
+
+struct ServerGame {
+  // Connections of white and black
+  players: [u32; 2],
+  names: [String; 2],
+  seek: Seek,
+  history: History,
+  clock: [Duration; 2],
+  // When the player to move started thinking
+  turn_start: Instant,
+  draw_offered: [bool; 2],
+  undo_requested: [bool; 2],
+  date: SystemTime,
+}
+
+// A game that has ended, ready to be saved
+struct Finished {
+  date: SystemTime,
+  white: String,
+  black: String,
+  seek: Seek,
+  // Only needed for the games table, the PTN has them otherwise
+  #[cfg(feature = "sqlite")]
+  moves: Vec<Move>,
+  result: Winner,
+  ptn: String,
+}
+
+// Protocol state, without any IO. Connections are identified by number, and
+// messages to send pile up in outbox.
+struct Server {
+  conns: BTreeMap<u32, Conn>,
+  // Seek number to the connection that posted it
+  seeks: BTreeMap<u32, (u32, Seek)>,
+  games: BTreeMap<u32, ServerGame>,
+  next_seek: u32,
+  next_game: u32,
+  next_guest: u32,
+  outbox: Vec<(u32, ServerMessage)>,
+  // Connections that have quit and should be closed
+  closing: Vec<u32>,
+  finished: Vec<Finished>,
+}
+
+impl Server {
+  fn new() -> Self {
+    Server {
+      conns: BTreeMap::new(),
+      seeks: BTreeMap::new(),
+      games: BTreeMap::new(),
+      next_seek: 1,
+      next_game: 1,
+      next_guest: 1,
+      outbox: Vec::new(),
+      closing: Vec::new(),
+      finished: Vec::new(),
+    }
+  }
+
+  fn send(&mut self, conn: u32, msg: ServerMessage) {
+    if self.conns.contains_key(&conn) {
+      self.outbox.push((conn, msg));
+    }
+  }
+
+  // Send to everyone logged in
+  fn broadcast(&mut self, msg: ServerMessage) {
+    let conns : Vec<u32> = self.conns.iter()
+      .filter(|&(_, c)| c.name.is_some())
+      .map(|(&id, _)| id)
+      .collect();
+    for conn in conns {
+      self.outbox.push((conn, msg.clone()));
+    }
+  }
+
+  fn connect(&mut self, conn: u32) {
+    self.conns.insert(conn, Conn { name: None, game: None });
+    self.send(conn, ServerMessage::Welcome);
+    self.send(conn, ServerMessage::LoginOrRegister);
+  }
+
+  fn disconnect(&mut self, conn: u32, now: Instant) {
+    self.remove_seeks(conn);
+    if let Some(id) = self.conns.get(&conn).and_then(|c| c.game) {
+      let s = self.games[&id].players.iter().position(|&p| p == conn).unwrap();
+      let opponent = self.games[&id].players[1 - s];
+      self.send(opponent, ServerMessage::Abandoned(id));
+      self.charge_clock(id, now);
+      self.finish(id, Winner::Other(color(1 - s)));
+    }
+    self.conns.remove(&conn);
+  }
+
+  fn handle(&mut self, conn: u32, line: &str, now: Instant) {
+    if line.trim().is_empty() {
+      return;
+    }
+    let msg = match playtak::parse_client_message(line) {
+      Ok(msg) => msg,
+      Err(_) => return self.send(conn, ServerMessage::Nok),
+    };
+    let name = match self.conns.get(&conn) {
+      Some(c) => c.name.clone(),
+      None => return,
+    };
+    match (msg, name) {
+      (ClientMessage::Client(_), _) => {},
+      (ClientMessage::Ping, _) => self.send(conn, ServerMessage::Ok),
+      (ClientMessage::Quit, _) => {
+        self.disconnect(conn, now);
+        self.closing.push(conn);
+      },
+      (ClientMessage::LoginGuest, None) => {
+        let name = format!("Guest{}", self.next_guest);
+        self.next_guest += 1;
+        self.login(conn, name);
+      },
+      (ClientMessage::Login(name, _), None) => {
+        // There are no accounts, so any unused name will do
+        let valid = !name.is_empty() && name.len() <= 20 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        let taken = self.conns.values().any(|c| c.name.as_ref() == Some(&name));
+        if valid && !taken && !name.starts_with("Guest") {
+          self.login(conn, name);
+        } else {
+          self.send(conn, ServerMessage::Nok);
+        }
+      },
+      (_, None) => self.send(conn, ServerMessage::Nok),
+      (ClientMessage::Login(..), Some(_)) | (ClientMessage::LoginGuest, Some(_)) => self.send(conn, ServerMessage::Nok),
+      (ClientMessage::Seek(seek), Some(name)) => {
+        if self.conns[&conn].game.is_some() {
+          return self.send(conn, ServerMessage::Nok);
+        }
+        self.remove_seeks(conn);
+        let id = self.next_seek;
+        self.next_seek += 1;
+        self.seeks.insert(id, (conn, seek));
+        self.broadcast(ServerMessage::SeekNew(id, name, seek));
+      },
+      (ClientMessage::Accept(id), Some(_)) => {
+        let available = match self.seeks.get(&id) {
+          Some(&(owner, _)) => owner != conn && self.conns[&owner].game.is_none() && self.conns[&conn].game.is_none(),
+          None => false,
+        };
+        if available {
+          self.start_game(id, conn, now);
+        } else {
+          self.send(conn, ServerMessage::Nok);
+        }
+      },
+      (ClientMessage::Shout(text), Some(name)) => self.broadcast(ServerMessage::Shout(name, text)),
+      (ClientMessage::Move(id, m), Some(_)) => self.play(conn, id, m, now),
+      (ClientMessage::OfferDraw(id), Some(_)) => {
+        if let Some((s, opponent)) = self.seat(conn, id) {
+          if self.games[&id].draw_offered[1 - s] {
+            self.charge_clock(id, now);
+            self.finish(id, Winner::Draw);
+          } else {
+            self.games.get_mut(&id).unwrap().draw_offered[s] = true;
+            self.send(opponent, ServerMessage::OfferDraw(id));
+          }
+        }
+      },
+      (ClientMessage::RemoveDraw(id), Some(_)) => {
+        if let Some((s, opponent)) = self.seat(conn, id) {
+          self.games.get_mut(&id).unwrap().draw_offered[s] = false;
+          self.send(opponent, ServerMessage::RemoveDraw(id));
+        }
+      },
+      (ClientMessage::RequestUndo(id), Some(_)) => {
+        if let Some((s, opponent)) = self.seat(conn, id) {
+          let agreed = {
+            let game = self.games.get_mut(&id).unwrap();
+            if game.undo_requested[1 - s] && game.history.undo() {
+              game.undo_requested = [false; 2];
+              true
+            } else {
+              game.undo_requested[s] = true;
+              false
+            }
+          };
+          if agreed {
+            self.send(conn, ServerMessage::Undo(id));
+            self.send(opponent, ServerMessage::Undo(id));
+          } else {
+            self.send(opponent, ServerMessage::RequestUndo(id));
+          }
+        }
+      },
+      (ClientMessage::RemoveUndo(id), Some(_)) => {
+        if let Some((s, opponent)) = self.seat(conn, id) {
+          self.games.get_mut(&id).unwrap().undo_requested[s] = false;
+          self.send(opponent, ServerMessage::RemoveUndo(id));
+        }
+      },
+      (ClientMessage::Resign(id), Some(_)) => {
+        if let Some((s, _)) = self.seat(conn, id) {
+          self.charge_clock(id, now);
+          self.finish(id, Winner::Other(color(1 - s)));
+        }
+      },
+    }
+  }
+
+  fn login(&mut self, conn: u32, name: String) {
+    self.conns.get_mut(&conn).unwrap().name = Some(name.clone());
+    self.send(conn, ServerMessage::LoggedIn(name));
+    let seeks : Vec<ServerMessage> = self.seeks.iter()
+      .map(|(&id, &(owner, seek))| {
+        let owner = self.conns[&owner].name.clone().unwrap();
+        ServerMessage::SeekNew(id, owner, seek)
+      })
+      .collect();
+    for msg in seeks {
+      self.send(conn, msg);
+    }
+  }
+
+  fn remove_seeks(&mut self, conn: u32) {
+    let ids : Vec<u32> = self.seeks.iter().filter(|&(_, &(owner, _))| owner == conn).map(|(&id, _)| id).collect();
+    for id in ids {
+      let (_, seek) = self.seeks.remove(&id).unwrap();
+      let name = self.conns[&conn].name.clone().unwrap();
+      self.broadcast(ServerMessage::SeekRemove(id, name, seek));
+    }
+  }
+
+  // Which side conn plays in game id, and the opponent's connection
+  fn seat(&self, conn: u32, id: u32) -> Option<(usize, u32)> {
+    let game = self.games.get(&id)?;
+    let s = game.players.iter().position(|&p| p == conn)?;
+    Some((s, game.players[1 - s]))
+  }
+
+  fn start_game(&mut self, seek_id: u32, acceptor: u32, now: Instant) {
+    let (owner, seek) = self.seeks[&seek_id];
+    let rules = Rules::new(seek.size).map(|r| Rules { komi: seek.komi, flats: seek.flats, caps: seek.caps, ..r });
+    let game = match rules.and_then(Game::with_rules) {
+      Some(game) => game,
+      None => return self.send(acceptor, ServerMessage::Nok),
+    };
+    self.remove_seeks(owner);
+    self.remove_seeks(acceptor);
+
+    let id = self.next_game;
+    self.next_game += 1;
+    // Without a preference, alternate who gets white
+    let owner_white = seek.color.map_or(id % 2 == 1, |c| c == Player::White);
+    let players = if owner_white { [owner, acceptor] } else { [acceptor, owner] };
+    let names = [self.conns[&players[0]].name.clone().unwrap(), self.conns[&players[1]].name.clone().unwrap()];
+    let time = Duration::from_secs(u64::from(seek.time));
+    for (s, &conn) in players.iter().enumerate() {
+      self.conns.get_mut(&conn).unwrap().game = Some(id);
+      self.send(conn, ServerMessage::GameStart {
+        game: id,
+        size: seek.size,
+        white: names[0].clone(),
+        black: names[1].clone(),
+        color: color(s),
+        time: seek.time,
+        komi: seek.komi,
+        flats: seek.flats,
+        caps: seek.caps,
+      });
+    }
+    self.games.insert(id, ServerGame {
+      players,
+      names,
+      seek,
+      history: History::new(game),
+      clock: [time; 2],
+      turn_start: now,
+      draw_offered: [false; 2],
+      undo_requested: [false; 2],
+      date: SystemTime::now(),
+    });
+  }
+
+  // Take the time since the player to move started thinking off their clock.
+  // Returns false if they've run out.
+  fn charge_clock(&mut self, id: u32, now: Instant) -> bool {
+    let game = self.games.get_mut(&id).unwrap();
+    let s = side(game.history.game().cur_player());
+    let elapsed = now.duration_since(game.turn_start);
+    game.turn_start = now;
+    match game.clock[s].checked_sub(elapsed) {
+      Some(left) => {
+        game.clock[s] = left;
+        true
+      },
+      None => {
+        game.clock[s] = Duration::from_secs(0);
+        false
+      },
+    }
+  }
+
+  fn send_time(&mut self, id: u32) {
+    let (players, clock) = {
+      let game = &self.games[&id];
+      (game.players, game.clock)
+    };
+    for &conn in players.iter() {
+      self.send(conn, ServerMessage::Time(id, clock[0].as_secs() as u32, clock[1].as_secs() as u32));
+    }
+  }
+
+  fn play(&mut self, conn: u32, id: u32, m: Move, now: Instant) {
+    let (s, opponent) = match self.seat(conn, id) {
+      Some(seat) => seat,
+      None => return self.send(conn, ServerMessage::Nok),
+    };
+    if side(self.games[&id].history.game().cur_player()) != s {
+      return self.send(conn, ServerMessage::Nok);
+    }
+    if !self.charge_clock(id, now) {
+      self.send_time(id);
+      return self.finish(id, Winner::Other(color(1 - s)));
+    }
+    let status = {
+      let game = self.games.get_mut(&id).unwrap();
+      if game.history.play(m).is_err() {
+        None
+      } else {
+        game.clock[s] += Duration::from_secs(u64::from(game.seek.increment));
+        game.undo_requested = [false; 2];
+        Some(game.history.game().status())
+      }
+    };
+    match status {
+      None => self.send(conn, ServerMessage::Nok),
+      Some(status) => {
+        self.send(opponent, ServerMessage::Move(id, m));
+        self.send_time(id);
+        if let Some(result) = status {
+          self.finish(id, result);
+        }
+      },
+    }
+  }
+
+  // End games where the player to move has run out of time
+  fn tick(&mut self, now: Instant) {
+    let flagged : Vec<(u32, Player)> = self.games.iter()
+      .filter(|&(_, g)| {
+        let s = side(g.history.game().cur_player());
+        now.duration_since(g.turn_start) >= g.clock[s]
+      })
+      .map(|(&id, g)| (id, g.history.game().cur_player()))
+      .collect();
+    for (id, player) in flagged {
+      self.charge_clock(id, now);
+      self.send_time(id);
+      self.finish(id, Winner::Other(player.opponent()));
+    }
+  }
+
+  fn finish(&mut self, id: u32, result: Winner) {
+    let game = match self.games.remove(&id) {
+      Some(game) => game,
+      None => return,
+    };
+    for &conn in game.players.iter() {
+      self.send(conn, ServerMessage::Over(id, result));
+      if let Some(c) = self.conns.get_mut(&conn) { c.game = None; }
+    }
+
+    let mut ptn = game.history.to_ptn();
+    ptn.result = Some(result);
+    ptn.player1 = game.names[0].clone();
+    ptn.player2 = game.names[1].clone();
+    let secs = game.date.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_date(secs / 86400);
+    let tags = [
+      ("date", format!("{}.{:02}.{:02}", year, month, day)),
+      ("clock", format!("{}:{:02} +{}", game.seek.time / 60, game.seek.time % 60, game.seek.increment)),
+    ];
+    for &(name, ref value) in tags.iter() {
+      ptn.tags.push(Tag { name: name.to_string(), value: value.clone() });
+    }
+    let [white, black] = game.names;
+    self.finished.push(Finished {
+      date: game.date,
+      white,
+      black,
+      seek: game.seek,
+      #[cfg(feature = "sqlite")]
+      moves: game.history.moves().to_vec(),
+      result,
+      ptn: ptn.to_string(),
+    });
+  }
+}
+
+// Year, month and day from days since 1970-01-01, after Howard Hinnant's
+// civil_from_days
+fn civil_date(days: u64) -> (u64, u64, u64) {
+  let z = days + 719_468;
+  let era = z / 146_097;
+  let doe = z - era * 146_097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+  (year, month, day)
+}
+
+// Where finished games are written
+struct Archive {
+  ptn_dir: Option<PathBuf>,
+  #[cfg(feature = "sqlite")]
+  db: Option<sqlite::Connection>,
+}
+
+impl Archive {
+  fn save(&self, game: &Finished) -> io::Result<()> {
+    if let Some(ref dir) = self.ptn_dir {
+      let secs = game.date.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+      let path = dir.join(format!("{}-{}-vs-{}.ptn", secs, game.white, game.black));
+      fs::write(path, &game.ptn)?;
+    }
+    #[cfg(feature = "sqlite")]
+    {
+      if let Some(ref db) = self.db {
+        save_to_db(db, game).map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
+      }
+    }
+    Ok(())
+  }
+}
+
+// Same columns as playtak's games table
+#[cfg(feature = "sqlite")]
+const CREATE_GAMES : &str = "
+  CREATE TABLE IF NOT EXISTS games (
+    id INTEGER PRIMARY KEY, date INT, size INT, player_white VARCHAR(20),
+    player_black VARCHAR(20), notation TEXT, result VARCHAR(10),
+    timertime INT DEFAULT 0, timerinc INT DEFAULT 0, rating_white INT DEFAULT 1000,
+    rating_black INT DEFAULT 1000, unrated INT DEFAULT 0, tournament INT DEFAULT 0,
+    komi INT DEFAULT 0, pieces INT DEFAULT -1, capstones INT DEFAULT -1,
+    rating_change_white INT DEFAULT 0, rating_change_black INT DEFAULT 0
+  )";
+
+#[cfg(feature = "sqlite")]
+fn save_to_db(db: &sqlite::Connection, game: &Finished) -> sqlite::Result<()> {
+  let mut insert = db.prepare("
+    INSERT INTO games (date, size, player_white, player_black, notation, result,
+      timertime, timerinc, unrated, tournament, komi, pieces, capstones)
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?;
+  let millis = game.date.duration_since(UNIX_EPOCH).map(|d| d.as_secs() * 1000).unwrap_or(0);
+  let notation : Vec<String> = game.moves.iter().map(playtak::to_string).collect();
+  insert.bind(1, millis as i64)?;
+  insert.bind(2, game.seek.size as i64)?;
+  insert.bind(3, game.white.as_str())?;
+  insert.bind(4, game.black.as_str())?;
+  insert.bind(5, notation.join(",").as_str())?;
+  insert.bind(6, playtak::result_to_string(game.result))?;
+  insert.bind(7, i64::from(game.seek.time))?;
+  insert.bind(8, i64::from(game.seek.increment))?;
+  insert.bind(9, i64::from(!game.seek.rated as u8))?;
+  insert.bind(10, i64::from(game.seek.tournament as u8))?;
+  insert.bind(11, i64::from(game.seek.komi.half_flats()))?;
+  insert.bind(12, i64::from(game.seek.flats))?;
+  insert.bind(13, i64::from(game.seek.caps))?;
+  while insert.next()? != sqlite::State::Done {}
+  Ok(())
+}
+
+enum Event {
+  Connected(u32, TcpStream),
+  Line(u32, String),
+  Closed(u32),
+}
+
+fn usage() -> ! {
+  eprintln!("usage: rustak-server [--port N] [--ptn DIR] [--db FILE]");
+  process::exit(2);
+}
+
+fn main() {
+  let mut port = 10000;
+  let mut archive = Archive {
+    ptn_dir: None,
+    #[cfg(feature = "sqlite")]
+    db: None,
+  };
+  let mut args = env::args().skip(1);
+  while let Some(arg) = args.next() {
+    let value = args.next().unwrap_or_else(|| usage());
+    match arg.as_str() {
+      "--port" => port = value.parse().unwrap_or_else(|_| usage()),
+      "--ptn" => {
+        fs::create_dir_all(&value).expect("can't create the PTN directory");
+        archive.ptn_dir = Some(PathBuf::from(value));
+      },
+      #[cfg(feature = "sqlite")]
+      "--db" => {
+        let db = sqlite::open(&value).expect("can't open the database");
+        db.execute(CREATE_GAMES).expect("can't create the games table");
+        archive.db = Some(db);
+      },
+      #[cfg(not(feature = "sqlite"))]
+      "--db" => {
+        eprintln!("rustak-server was built without the sqlite feature");
+        process::exit(2);
+      },
+      _ => usage(),
+    }
+  }
+
+  let listener = TcpListener::bind(("0.0.0.0", port)).expect("can't listen on the port");
+  eprintln!("listening on port {}", port);
+  let (tx, rx) = mpsc::channel();
+  thread::spawn(move || {
+    for (id, stream) in listener.incoming().enumerate() {
+      let stream = match stream {
+        Ok(stream) => stream,
+        Err(_) => continue,
+      };
+      let id = id as u32;
+      let tx = tx.clone();
+      let reader = match stream.try_clone() {
+        Ok(reader) => reader,
+        Err(_) => continue,
+      };
+      if tx.send(Event::Connected(id, stream)).is_err() {
+        return;
+      }
+      thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+          match line {
+            Ok(line) => if tx.send(Event::Line(id, line)).is_err() { return },
+            Err(_) => break,
+          }
+        }
+        let _ = tx.send(Event::Closed(id));
+      });
+    }
+  });
+
+  let mut server = Server::new();
+  let mut streams = BTreeMap::new();
+  loop {
+    let now = Instant::now();
+    match rx.recv_timeout(Duration::from_millis(100)) {
+      Ok(Event::Connected(id, stream)) => {
+        streams.insert(id, stream);
+        server.connect(id);
+      },
+      Ok(Event::Line(id, line)) => server.handle(id, &line, now),
+      Ok(Event::Closed(id)) => {
+        server.disconnect(id, now);
+        streams.remove(&id);
+      },
+      Err(mpsc::RecvTimeoutError::Timeout) => {},
+      Err(mpsc::RecvTimeoutError::Disconnected) => return,
+    }
+    server.tick(Instant::now());
+
+    for (id, msg) in server.outbox.drain(..) {
+      let failed = match streams.get_mut(&id) {
+        Some(stream) => writeln!(stream, "{}", msg).is_err(),
+        None => false,
+      };
+      if failed {
+        streams.remove(&id);
+      }
+    }
+    for id in server.closing.drain(..) {
+      if let Some(stream) = streams.remove(&id) {
+        let _ = stream.shutdown(::std::net::Shutdown::Both);
+      }
+    }
+    for game in server.finished.drain(..) {
+      eprintln!("{}x{} {} vs {}: {}", game.seek.size, game.seek.size, game.white, game.black, playtak::result_to_string(game.result));
+      if let Err(e) = archive.save(&game) {
+        eprintln!("couldn't save game: {}", e);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{civil_date, Server};
+  use std::time::{Duration, Instant};
+  use rustak::client::{Bot, Client, Config, Mode, OnlineGame};
+  use rustak::game::{Move, Player, Winner};
+  use rustak::playtak::{parse_client_message, Seek, ServerMessage};
+  use rustak::ptn;
+
+  // Messages sent to conn since the last call, as text
+  fn sent(server: &mut Server, conn: u32) -> Vec<String> {
+    let (mine, rest) = server.outbox.drain(..).partition(|&(c, _)| c == conn);
+    server.outbox = rest;
+    mine.into_iter().map(|(_, msg): (u32, ServerMessage)| msg.to_string()).collect()
+  }
+
+  fn login(server: &mut Server, conn: u32, name: &str, now: Instant) {
+    server.connect(conn);
+    server.handle(conn, &format!("Login {} x", name), now);
+    assert_eq!(sent(server, conn), vec!["Welcome!", "Login or Register", &format!("Welcome {}!", name)]);
+  }
+
+  // alice seeks a 5x5 game as white, and bob accepts it as game 1
+  fn start(server: &mut Server, now: Instant) {
+    login(server, 1, "alice", now);
+    login(server, 2, "bob", now);
+    server.handle(1, "Seek 5 60 5 W 0 21 1 0 0", now);
+    assert_eq!(sent(server, 2), vec!["Seek new 1 alice 5 60 5 W 0 21 1 0 0"]);
+    server.handle(2, "Accept 1", now);
+    assert_eq!(sent(server, 1), vec![
+      "Seek new 1 alice 5 60 5 W 0 21 1 0 0",
+      "Seek remove 1 alice 5 60 5 W 0 21 1 0 0",
+      "Game Start 1 5 alice vs bob white 60 0 21 1",
+    ]);
+    assert_eq!(sent(server, 2), vec![
+      "Seek remove 1 alice 5 60 5 W 0 21 1 0 0",
+      "Game Start 1 5 alice vs bob black 60 0 21 1",
+    ]);
+  }
+
+  #[test]
+  fn play_game() {
+    let now = Instant::now();
+    let mut server = Server::new();
+    start(&mut server, now);
+
+    // Moves are relayed with the clocks, and illegal ones are refused
+    server.handle(1, "Game#1 P E5", now + Duration::from_secs(3));
+    assert_eq!(sent(&mut server, 2), vec!["Game#1 P E5", "Game#1 Time 62 60"]);
+    assert_eq!(sent(&mut server, 1), vec!["Game#1 Time 62 60"]);
+    server.handle(1, "Game#1 P B1", now + Duration::from_secs(4));
+    server.handle(2, "Game#1 P E5", now + Duration::from_secs(4));
+    server.handle(2, "Game#1 M E5 E4 1", now + Duration::from_secs(4));
+    assert_eq!(sent(&mut server, 1), vec!["NOK"]);
+    assert_eq!(sent(&mut server, 2), vec!["NOK", "NOK"]);
+
+    let moves = ["Game#1 P A1", "Game#1 P B1", "Game#1 P E4", "Game#1 P C1", "Game#1 P E3", "Game#1 P D1", "Game#1 P E2"];
+    for (i, m) in moves.iter().enumerate() {
+      server.handle(2 - (i as u32 % 2), m, now + Duration::from_secs(5));
+    }
+    server.handle(1, "Game#1 P E1", now + Duration::from_secs(6));
+    assert_eq!(sent(&mut server, 2).last().unwrap(), "Game#1 Over R-0");
+    assert!(server.games.is_empty());
+
+    let game = server.finished.pop().unwrap();
+    assert_eq!(game.result, Winner::Road(Player::White));
+    assert_eq!(ptn::parse(&game.ptn).unwrap().moves.len(), 9);
+    assert!(game.ptn.contains("[Player1 \"alice\"]"));
+    assert!(game.ptn.contains("[Clock \"1:00 +5\"]"));
+    assert!(game.ptn.ends_with("5. e1\nR-0\n"), "{}", game.ptn);
+  }
+
+  #[test]
+  fn ending_games() {
+    let now = Instant::now();
+    let mut server = Server::new();
+
+    // Resigning
+    start(&mut server, now);
+    server.handle(2, "Game#1 Resign", now);
+    assert_eq!(sent(&mut server, 1), vec!["Game#1 Over 1-0"]);
+
+    // Agreeing to a draw, after taking back a move
+    server.handle(1, "Seek 5 60 5 B 0 21 1 0 0", now);
+    server.handle(2, "Accept 2", now);
+    server.outbox.clear();
+    server.handle(2, "Game#2 P A1", now);
+    server.handle(1, "Game#2 RequestUndo", now);
+    server.handle(2, "Game#2 RequestUndo", now);
+    assert_eq!(sent(&mut server, 1), vec!["Game#2 P A1", "Game#2 Time 65 60", "Game#2 Undo"]);
+    assert_eq!(server.games[&2].history.ply(), 0);
+    server.handle(2, "Game#2 OfferDraw", now);
+    server.handle(2, "Game#2 RemoveDraw", now);
+    server.handle(1, "Game#2 OfferDraw", now);
+    assert_eq!(sent(&mut server, 1), vec!["Game#2 OfferDraw", "Game#2 RemoveDraw"]);
+    server.handle(2, "Game#2 OfferDraw", now);
+    assert_eq!(sent(&mut server, 1), vec!["Game#2 Over 1/2-1/2"]);
+
+    // Running out of time
+    server.handle(1, "Seek 5 60 5 W 0 21 1 0 0", now);
+    server.handle(2, "Accept 3", now);
+    server.outbox.clear();
+    server.tick(now + Duration::from_secs(59));
+    assert!(server.outbox.is_empty());
+    server.tick(now + Duration::from_secs(61));
+    assert_eq!(sent(&mut server, 2), vec!["Game#3 Time 0 60", "Game#3 Over 0-1"]);
+
+    // Leaving
+    server.handle(1, "Seek 5 60 5 W 0 21 1 0 0", now);
+    server.handle(2, "Accept 4", now);
+    server.outbox.clear();
+    server.handle(1, "quit", now);
+    assert_eq!(sent(&mut server, 2), vec!["Game#4 Abandoned", "Game#4 Over 0-1"]);
+    assert_eq!(server.closing, vec![1]);
+    let results : Vec<Winner> = server.finished.iter().map(|g| g.result).collect();
+    assert_eq!(results, vec![Winner::Other(Player::White), Winner::Draw, Winner::Other(Player::Black), Winner::Other(Player::Black)]);
+  }
+
+  #[test]
+  fn logins() {
+    let now = Instant::now();
+    let mut server = Server::new();
+    login(&mut server, 1, "alice", now);
+    server.connect(2);
+    server.handle(2, "Seek 5 60 5", now);
+    server.handle(2, "Login alice x", now);
+    server.handle(2, "Login Guest", now);
+    server.handle(2, "Login Guest", now);
+    server.handle(2, "Shout hi all", now);
+    server.handle(2, "PING", now);
+    assert_eq!(sent(&mut server, 2)[2 ..].to_vec(), vec!["NOK", "NOK", "Welcome Guest1!", "NOK", "Shout <Guest1> hi all", "OK"]);
+    assert_eq!(sent(&mut server, 1), vec!["Shout <Guest1> hi all"]);
+  }
+
+  struct FirstMove;
+
+  impl Bot for FirstMove {
+    fn choose_move(&mut self, online: &OnlineGame) -> Move {
+      online.game.moves().next().unwrap()
+    }
+  }
+
+  // The client library can play a whole game through the server
+  #[test]
+  fn bots() {
+    let now = Instant::now();
+    let mut server = Server::new();
+    let seek = Seek::new(4, 600, 0).unwrap();
+    let mut clients = [
+      Client::new(Config { client_name: "test".to_string(), login: None, mode: Mode::Seek(seek) }, FirstMove),
+      Client::new(Config { client_name: "test".to_string(), login: None, mode: Mode::Accept(None) }, FirstMove),
+    ];
+    server.connect(0);
+    server.connect(1);
+    loop {
+      let outbox : Vec<(u32, ServerMessage)> = server.outbox.drain(..).collect();
+      if outbox.is_empty() {
+        break;
+      }
+      for (conn, msg) in outbox {
+        let replies = clients[conn as usize].handle(&msg.to_string()).unwrap();
+        // Once the game is over, don't let the clients start another one
+        if !server.finished.is_empty() {
+          continue;
+        }
+        for reply in replies {
+          let line = reply.to_string();
+          assert_eq!(parse_client_message(&line).unwrap(), reply);
+          server.handle(conn, &line, now);
+        }
+      }
+    }
+    assert_eq!(server.finished.len(), 1);
+    let game = &server.finished[0];
+    assert!(ptn::parse(&game.ptn).unwrap().moves.len() > 4);
+    assert_eq!(clients[0].games_played(), 1);
+    assert_eq!(clients[1].games_played(), 1);
+    assert!(ptn::parse(&game.ptn).is_ok());
+  }
+
+  #[test]
+  fn dates() {
+    assert_eq!(civil_date(0), (1970, 1, 1));
+    assert_eq!(civil_date(11_016), (2000, 2, 29));
+    assert_eq!(civil_date(17_897), (2019, 1, 1));
+  }
+}
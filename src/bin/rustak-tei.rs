@@ -0,0 +1,307 @@
+// Tak Engine Interface (TEI) front end for the built-in engine, so it can be
+// run by tournament managers and GUIs. TEI follows UCI closely, with moves
+// and positions written in PTN and TPS.
+
+extern crate rustak;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rustak::book::Book;
+use rustak::engine::{self, Engine, Limits, SearchResult};
+use rustak::eval::Handcrafted;
+use rustak::game::{Game, Komi, MoveValidity, Player, Rules};
+use rustak::{ptn, tps};
+
+struct Tei {
+  size: usize,
+  half_komi: u8,
+  game: Option<Game>,
+  // The engine is moved onto the search thread while it's searching
+  engine: Option<Engine<Handcrafted>>,
+  search: Option<JoinHandle<Engine<Handcrafted>>>,
+  stop: Arc<AtomicBool>,
+  // Moves are taken from the book, when it has any, instead of searching
+  book: Option<Book>,
+}
+
+impl Tei {
+  fn new() -> Self {
+    let engine = Engine::new(Handcrafted::default());
+    Tei {
+      size: 5,
+      half_komi: 0,
+      game: None,
+      stop: engine.stop_handle(),
+      engine: Some(engine),
+      search: None,
+      book: None,
+    }
+  }
+
+  fn rules(&self) -> Option<Rules> {
+    Some(Rules { komi: Komi::from_half_flats(self.half_komi), ..Rules::new(self.size)? })
+  }
+
+  // Wait for the current search, if any, to finish
+  fn wait(&mut self) {
+    if let Some(search) = self.search.take() {
+      self.engine = Some(search.join().expect("search thread panicked"));
+    }
+    // A stop that came in after the search ended is stale
+    self.stop.store(false, Ordering::Relaxed);
+  }
+
+  fn stop(&mut self) {
+    if self.search.is_some() {
+      self.stop.store(true, Ordering::Relaxed);
+    }
+    self.wait();
+  }
+
+  // Handle one line of input. Returns false on quit.
+  fn command(&mut self, line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    match words.next() {
+      Some("tei") => {
+        println!("id name rustak {}", env!("CARGO_PKG_VERSION"));
+        println!("id author {}", env!("CARGO_PKG_AUTHORS"));
+        println!("option name HalfKomi type spin default 0 min 0 max 20");
+        println!("option name Book type string default <empty>");
+        println!("teiok");
+      },
+      Some("isready") => println!("readyok"),
+      Some("setoption") => self.set_option(&words.collect::<Vec<_>>()),
+      Some("teinewgame") => {
+        self.stop();
+        match words.next().and_then(|s| s.parse().ok()) {
+          Some(size) if Rules::new(size).is_some() => self.size = size,
+          _ => println!("info string invalid board size"),
+        }
+        self.game = None;
+        if let Some(engine) = self.engine.as_mut() { engine.clear(); }
+      },
+      Some("position") => {
+        self.wait();
+        match self.position(&words.collect::<Vec<_>>()) {
+          Ok(game) => self.game = Some(game),
+          Err(e) => {
+            self.game = None;
+            println!("info string {}", e);
+          },
+        }
+      },
+      Some("go") => {
+        self.wait();
+        let words : Vec<_> = words.collect();
+        self.go(&words);
+      },
+      Some("stop") => self.stop(),
+      // Not part of TEI, but handy for debugging
+      Some("d") => match self.game {
+        Some(ref game) => println!("{}", game.board()),
+        None => println!("info string no position"),
+      },
+      Some("quit") => {
+        self.stop();
+        return false;
+      },
+      Some(cmd) => println!("info string unknown command {}", cmd),
+      None => {},
+    }
+    true
+  }
+
+  fn set_option(&mut self, words: &[&str]) {
+    // setoption name <name> value <value>, where the value may have spaces
+    let split = words.iter().position(|&w| w == "value").unwrap_or(words.len());
+    let value = words.get(split + 1 ..).map_or(String::new(), |v| v.join(" "));
+    match (words.first(), words.get(1 .. split).unwrap_or(&[])) {
+      (Some(&"name"), &["HalfKomi"]) => match value.parse() {
+        Ok(half_komi) => self.half_komi = half_komi,
+        Err(_) => println!("info string invalid komi {}", value),
+      },
+      (Some(&"name"), &["Book"]) if value.is_empty() || value == "<empty>" => self.book = None,
+      (Some(&"name"), &["Book"]) => {
+        let book = File::open(&value).and_then(|f| Book::read(&mut BufReader::new(f)));
+        match book {
+          Ok(book) => self.book = Some(book),
+          Err(e) => println!("info string can't read book {}: {}", value, e),
+        }
+      },
+      _ => println!("info string unknown option {}", words.join(" ")),
+    }
+  }
+
+  // position startpos [moves ...] or position tps <tps> [moves ...]
+  fn position(&self, words: &[&str]) -> Result<Game, String> {
+    let rules = self.rules().ok_or("invalid board size")?;
+    let split = words.iter().position(|&w| w == "moves").unwrap_or(words.len());
+    let mut game = match words.split_first() {
+      Some((&"startpos", _)) => Game::with_rules(rules).ok_or("invalid rules")?,
+      Some((&"tps", tps)) => {
+        let tps = tps[.. split - 1].join(" ");
+        tps::parse_with_rules(&tps, rules).map_err(|e| format!("invalid tps {}: {:?}", tps, e))?
+      },
+      _ => return Err(format!("invalid position {}", words.join(" "))),
+    };
+    for &word in words.iter().skip(split + 1) {
+      let m = ptn::parse_move(word).ok_or_else(|| format!("invalid move {}", word))?;
+      match game.validate(&m) {
+        MoveValidity::Valid => { game.execute(&m); },
+        v => return Err(format!("illegal move {}: {:?}", word, v)),
+      }
+    }
+    Ok(game)
+  }
+
+  fn go(&mut self, words: &[&str]) {
+    let game = match self.game.clone() {
+      Some(game) => game,
+      None => {
+        println!("info string no position");
+        return;
+      },
+    };
+    // Any random number will do for picking between book moves
+    let random = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    if let Some(m) = self.book.as_ref().and_then(|book| book.choose(&game, 1, u64::from(random))) {
+      println!("info string book move");
+      println!("bestmove {}", ptn::to_string(&m));
+      return;
+    }
+    let limits = limits(words, game.cur_player());
+    let mut engine = self.engine.take().expect("engine is searching");
+    self.search = Some(thread::spawn(move || {
+      let res = engine.search_with_info(&game, limits, |info| println!("{}", format_info(info)));
+      match res.best {
+        Some(m) => println!("bestmove {}", ptn::to_string(&m)),
+        None => println!("bestmove none"),
+      }
+      engine
+    }));
+  }
+}
+
+// Search limits from the arguments to go
+fn limits(words: &[&str], player: Player) -> Limits {
+  let mut limits = Limits::default();
+  let (mut time, mut inc) = (None, 0);
+  let mut args = words.iter();
+  fn value(args: &mut ::std::slice::Iter<&str>) -> Option<u64> {
+    args.next().and_then(|v| v.parse().ok())
+  }
+  while let Some(&word) = args.next() {
+    match (word, player) {
+      ("movetime", _) => limits.time = value(&mut args).map(Duration::from_millis),
+      ("depth", _) => limits.depth = value(&mut args).map(|d| d as u32),
+      ("nodes", _) => limits.nodes = value(&mut args),
+      ("wtime", Player::White) | ("btime", Player::Black) => time = value(&mut args),
+      ("winc", Player::White) | ("binc", Player::Black) => inc = value(&mut args).unwrap_or(0),
+      ("wtime", _) | ("btime", _) | ("winc", _) | ("binc", _) => { args.next(); },
+      _ => {},
+    }
+  }
+  // With a clock, spend a slice of what's left plus most of the increment
+  if let (None, Some(time)) = (limits.time, time) {
+    let budget = (time / 20 + inc * 3 / 4).min(time / 2);
+    limits.time = Some(Duration::from_millis(budget.max(1)));
+  }
+  limits
+}
+
+fn format_info(info: &SearchResult) -> String {
+  let score = if engine::is_win_score(info.score) {
+    // Plies to the win, converted to moves
+    let plies = engine::WIN - info.score.abs();
+    let moves = (plies + 1) / 2;
+    format!("mate {}", if info.score > 0 { moves } else { -moves })
+  } else {
+    format!("cp {}", info.score)
+  };
+  let time = info.time.as_secs() * 1000 + u64::from(info.time.subsec_millis());
+  let pv : Vec<String> = info.pv.iter().map(ptn::to_string).collect();
+  format!("info depth {} score {} nodes {} time {} pv {}", info.depth, score, info.nodes, time, pv.join(" "))
+}
+
+fn main() {
+  let mut tei = Tei::new();
+  let stdin = io::stdin();
+  for line in stdin.lock().lines() {
+    let line = match line {
+      Ok(line) => line,
+      Err(_) => break,
+    };
+    if !tei.command(&line) {
+      return;
+    }
+  }
+  // Let a search started just before the input ended finish
+  tei.wait();
+}
+
+#[cfg(test)]
+mod test {
+  use super::{limits, Tei};
+  use std::env;
+  use std::fs::File;
+  use std::time::Duration;
+  use rustak::book::Book;
+  use rustak::game::{self, Player, Winner};
+  use rustak::{ptn, tps};
+
+  #[test]
+  fn position() {
+    let mut tei = Tei::new();
+    let g = tei.position(&["startpos", "moves", "a1", "e5", "Cc3"]).unwrap();
+    assert_eq!(tps::to_string(&g), "x4,1/x5/x2,1C,x2/x5/2,x4 2 2");
+    let g = tei.position(&["tps", "x5/x5/x5/x5/2,x4", "2", "1", "moves", "e5"]).unwrap();
+    assert_eq!(tps::to_string(&g), "x4,1/x5/x5/x5/2,x4 1 2");
+    assert!(tei.position(&["startpos", "moves", "a1+"]).is_err());
+
+    tei.command("teinewgame 6");
+    tei.command("setoption name HalfKomi value 4");
+    let g = tei.position(&["startpos"]).unwrap();
+    assert_eq!((g.size(), g.komi().half_flats()), (6, 4));
+  }
+
+  #[test]
+  fn go_limits() {
+    let args = ["wtime", "10000", "btime", "2000", "winc", "100", "binc", "0"];
+    assert_eq!(limits(&args, Player::White).time, Some(Duration::from_millis(575)));
+    assert_eq!(limits(&args, Player::Black).time, Some(Duration::from_millis(100)));
+    let l = limits(&["movetime", "300", "depth", "4", "wtime", "10000"], Player::White);
+    assert_eq!((l.time, l.depth), (Some(Duration::from_millis(300)), Some(4)));
+  }
+
+  #[test]
+  fn book() {
+    let mut book = Book::new(5, 10);
+    let m = ptn::parse_move("a1").unwrap();
+    book.add_game(&game::new(5).unwrap(), &[m], Winner::Draw);
+    let path = env::temp_dir().join("rustak-tei-test.book");
+    book.write(&mut File::create(&path).unwrap()).unwrap();
+
+    let mut tei = Tei::new();
+    tei.command(&format!("setoption name Book value {}", path.display()));
+    let g = tei.position(&["startpos"]).unwrap();
+    assert_eq!(tei.book.as_ref().unwrap().moves(&g).len(), 1);
+    tei.command("setoption name Book value <empty>");
+    assert!(tei.book.is_none());
+    tei.command("setoption name Book value /nonexistent/rustak.book");
+    assert!(tei.book.is_none());
+  }
+
+  #[test]
+  fn bad_options() {
+    let mut tei = Tei::new();
+    tei.command("setoption");
+    tei.command("setoption value 4");
+    tei.command("setoption name");
+    tei.command("setoption name HalfKomi value x");
+    assert_eq!(tei.half_komi, Tei::new().half_komi);
+  }
+}
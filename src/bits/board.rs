@@ -0,0 +1,156 @@
+use std::fmt;
+use game::{Loc, Piece, Player};
+use bits::Game;
+
+// Draws a game as a grid for reading in a terminal, i.e.
+//   println!("{}", game.board().colors(true));
+// Each square shows its stack from the bottom up, 1 for white and 2 for black,
+// followed by S or C if a wall or capstone is on top, as in TPS.
+#[derive(Debug,Clone,Copy)]
+pub struct Board<'a> {
+  game: &'a Game,
+  colors: bool,
+  flipped: bool,
+}
+
+impl Game {
+  pub fn board(&self) -> Board {
+    Board { game: self, colors: false, flipped: false }
+  }
+}
+
+impl<'a> Board<'a> {
+  // Color pieces with ANSI escapes
+  pub fn colors(self, colors: bool) -> Self {
+    Board { colors, ..self }
+  }
+
+  // Turn the board around, so it's seen from black's side
+  pub fn flipped(self, flipped: bool) -> Self {
+    Board { flipped, ..self }
+  }
+
+  fn square(&self, loc: Loc) -> (String, usize) {
+    let stack = self.game.stack(loc);
+    if stack.is_empty() {
+      return (".".to_string(), 1);
+    }
+    let mut text = String::new();
+    for p in stack.into_iter().rev() {
+      let digit = match p { Player::White => '1', Player::Black => '2' };
+      if self.colors {
+        text.push_str(color(p));
+        text.push(digit);
+        text.push_str(RESET);
+      } else {
+        text.push(digit);
+      }
+    }
+    match self.game.top(loc) {
+      Some(Piece::Wall) => text.push('S'),
+      Some(Piece::Cap) => text.push('C'),
+      _ => {},
+    }
+    let len = stack.len() + if self.game.top(loc) == Some(Piece::Flat) { 0 } else { 1 };
+    (text, len)
+  }
+}
+
+const RESET : &str = "\x1b[0m";
+
+fn color(p: Player) -> &'static str {
+  match p {
+    Player::White => "\x1b[1;33m",
+    Player::Black => "\x1b[1;34m",
+  }
+}
+
+fn name(p: Player) -> &'static str {
+  match p {
+    Player::White => "White",
+    Player::Black => "Black",
+  }
+}
+
+impl<'a> fmt::Display for Board<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let size = self.game.size();
+    // Board coordinates of the rows from the top and the columns from the left
+    let order = |i: usize| if self.flipped { i } else { size - 1 - i };
+    let column = |i: usize| if self.flipped { size - 1 - i } else { i };
+
+    let mut rows = Vec::new();
+    for i in 0 .. size {
+      let y = order(i);
+      let row : Vec<(String, usize)> = (0 .. size)
+        .map(|j| self.square(Loc { x: column(j) as u8, y: y as u8 }))
+        .collect();
+      rows.push((y, row));
+    }
+    let width = rows.iter().flat_map(|r| r.1.iter().map(|&(_, len)| len)).max().unwrap_or(1);
+
+    for (y, row) in rows {
+      write!(f, "{:>2} ", y + 1)?;
+      for (j, (text, len)) in row.into_iter().enumerate() {
+        write!(f, " {}", text)?;
+        if j + 1 < size {
+          write!(f, "{:1$}", "", width - len)?;
+        }
+      }
+      writeln!(f)?;
+    }
+    write!(f, "   ")?;
+    for j in 0 .. size {
+      let file = (b'a' + column(j) as u8) as char;
+      write!(f, " {}", file)?;
+      if j + 1 < size {
+        write!(f, "{:1$}", "", width - 1)?;
+      }
+    }
+    writeln!(f)?;
+
+    for &p in [Player::White, Player::Black].iter() {
+      let reserves = self.game.reserves(p);
+      let label = if self.colors { format!("{}{}{}", color(p), name(p), RESET) } else { name(p).to_string() };
+      writeln!(f, "{}: {} flats, {} caps", label, reserves.count(Piece::Flat), reserves.count(Piece::Cap))?;
+    }
+    write!(f, "{} to move, move {}", name(self.game.cur_player()), self.game.round())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use tps;
+
+  #[test]
+  fn board() {
+    let g = tps::parse("x4,1/x5/x,2,12C,x2/x5/2S,x4 1 3").unwrap();
+    let lines = [
+      " 5  .   .   .   .   1",
+      " 4  .   .   .   .   .",
+      " 3  .   2   12C .   .",
+      " 2  .   .   .   .   .",
+      " 1  2S  .   .   .   .",
+      "    a   b   c   d   e",
+      "White: 19 flats, 1 caps",
+      "Black: 19 flats, 0 caps",
+      "White to move, move 3",
+    ];
+    assert_eq!(g.board().to_string(), lines.join("\n"));
+
+    let flipped = g.board().flipped(true).to_string();
+    let flipped : Vec<&str> = flipped.lines().collect();
+    assert_eq!(flipped[0], " 1  .   .   .   .   2S");
+    assert_eq!(flipped[2], " 3  .   .   12C 2   .");
+    assert_eq!(flipped[5], "    e   d   c   b   a");
+
+    // Colors don't change the layout
+    let colored = g.board().colors(true).to_string();
+    assert!(colored.contains("\x1b[1;33m1\x1b[0m\x1b[1;34m2\x1b[0mC .   ."));
+    let plain = colored.replace("\x1b[1;33m", "").replace("\x1b[1;34m", "").replace("\x1b[0m", "");
+    assert_eq!(plain, lines.join("\n"));
+
+    let g = tps::parse("x3/x3/x3 2 1").unwrap();
+    assert_eq!(g.board().to_string().lines().next(), Some(" 3  . . ."));
+  }
+}
@@ -3,6 +3,7 @@ use ::fnv64::{self,Fnv64};
 use ::game::*;
 use std::cmp::min;
 use bits::BinConv;
+use tables::{drop_counts, DropCount, TableIter};
 
 #[derive(Debug,Clone)]
 pub struct Game {
@@ -15,6 +16,7 @@ pub struct Game {
 
   white_reserves: Reserves,
   black_reserves: Reserves,
+  rules: Rules,
 
   left_mask: u64,
   right_mask: u64,
@@ -34,7 +36,18 @@ pub struct Game {
 
 impl Game {
   pub fn new(size: usize) -> Option<Self> {
+    Game::with_rules(Rules::new(size)?)
+  }
+
+  pub fn with_komi(size: usize, komi: Komi) -> Option<Self> {
+    Game::with_rules(Rules { komi, ..Rules::new(size)? })
+  }
+
+  pub fn with_rules(rules: Rules) -> Option<Self> {
+    let size = rules.size;
     if size < 3 || size > 8 { return None }
+    if rules.carry_limit == 0 || rules.carry_limit as usize > size { return None }
+    if rules.flats == 0 { return None }
 
     let mut left_mask = 1u64;
     for _ in 1..size { left_mask |= left_mask << size; }
@@ -43,8 +56,9 @@ impl Game {
         size,
         round: 1,
         player: Player::White,
-        white_reserves: Reserves::new(size).unwrap(),
-        black_reserves: Reserves::new(size).unwrap(),
+        white_reserves: Reserves::with_counts(rules.flats, rules.caps),
+        black_reserves: Reserves::with_counts(rules.flats, rules.caps),
+        rules,
         left_mask,
         right_mask: left_mask << (size-1),
         top_mask: ((1u64<<size)-1)<<(size*size-size),
@@ -165,17 +179,31 @@ impl Game {
         let idx = self.idx(loc);
         if !self.owners[idx].is_empty() { return MoveValidity::SquareOccupied; }
         if self.reserves(self.player).count(piece) == 0 { return MoveValidity::NotEnough(piece); }
-        if self.round == 1 && piece != Piece::Flat {
+        if self.opening_swap() && piece != Piece::Flat {
           return MoveValidity::MustPlaceFlatFirstRound;
         }
         MoveValidity::Valid
       },
-      Move::Move(start, dir, range, ref drop_counts, _) => {
+      Move::Move(start, dir, range, ref drop_counts) => {
         if start.x as usize >= self.size || start.y as usize >= self.size { return MoveValidity::InvalidSquare; }
-        if self.round == 1 { return MoveValidity::MustPlaceFlatFirstRound; }
+        if self.opening_swap() { return MoveValidity::MustPlaceFlatFirstRound; }
+        if range == 0 || range as usize > drop_counts.len() || drop_counts[.. range as usize].contains(&0) {
+          return MoveValidity::InvalidDropCounts;
+        }
         let start_idx = self.idx(start);
         if self.owners[start_idx].is_empty() { return MoveValidity::DontControlStack; }
-        if self.offset(start_idx,dir,range) > (self.size * self.size) { return MoveValidity::EndOutOfBounds; }
+        // Checked on coordinates rather than indices, so moves can't wrap around
+        // to the next row or underflow off the bottom of the board
+        let (x, y, range_i) = (start.x as i32, start.y as i32, range as i32);
+        let (end_x, end_y) = match dir {
+          Dir::Up => (x, y + range_i),
+          Dir::Down => (x, y - range_i),
+          Dir::Left => (x - range_i, y),
+          Dir::Right => (x + range_i, y),
+        };
+        if end_x < 0 || end_y < 0 || end_x as usize >= self.size || end_y as usize >= self.size {
+          return MoveValidity::EndOutOfBounds;
+        }
         if self.owners[start_idx].get(0) != self.player { return MoveValidity::DontControlStack; }
         let is_cap = self.get_top(start_idx) == Piece::Cap;
         let mut pieces_moved = 0;
@@ -202,7 +230,7 @@ impl Game {
           if pieces_moved > self.owners[start_idx].len() as u8 {
             return MoveValidity::NotEnoughPieces;
           }
-          if pieces_moved > self.size as u8 {
+          if pieces_moved > self.rules.carry_limit {
             return MoveValidity::CarryLimit;
           }
         }
@@ -211,23 +239,24 @@ impl Game {
     }
   }
 
-  pub fn execute(&mut self, m: &mut Move) {
+  pub fn execute(&mut self, m: &Move) -> Undo {
+    let mut smash = false;
     match *m {
       Move::Place(loc, piece) => {
         let idx = self.idx(loc);
         self.update_hash(idx);
-        let player = if self.round == 1 { self.player.opponent() } else { self.player };
+        let player = if self.opening_swap() { self.player.opponent() } else { self.player };
         self.reserves_mut(player).remove(piece);
         self.owners[idx].push(player);
         self.fix_top(idx);
         self.set_top(idx, piece);
         self.update_hash(idx);
       },
-      Move::Move(start, dir, range, ref drop_counts, ref mut smash) => {
+      Move::Move(start, dir, range, ref drop_counts) => {
         let start_idx = self.idx(start);
         let end_idx = self.offset(start_idx, dir, range);
         self.update_hash(start_idx);
-        *smash = self.walls & (1<<end_idx) != 0;
+        smash = self.walls & (1<<end_idx) != 0;
         let top = self.get_top(start_idx);
         for i in (1 ..= range).rev() {
           let idx = self.offset(start_idx,dir,i);
@@ -244,19 +273,20 @@ impl Game {
     if self.player == Player::White {
       self.round += 1;
     }
+    Undo { m: *m, smash }
   }
 
-  pub fn undo(&mut self, m: &Move) {
+  pub fn undo(&mut self, undo: &Undo) {
     if self.player == Player::White {
       self.round -= 1;
     }
     self.player = self.player.opponent();
 
-    match *m {
+    match undo.m {
       Move::Place(loc, piece) => {
         let idx = self.idx(loc);
         self.update_hash(idx);
-        let player = if self.round == 1 { self.player } else { self.player.opponent() };
+        let player = if self.opening_swap() { self.player.opponent() } else { self.player };
         self.reserves_mut(player).add(piece);
         let _ = self.owners[idx].pop_stack(1);
         self.walls &= !(1<<idx);
@@ -265,7 +295,7 @@ impl Game {
         self.black &= !(1<<idx);
         self.update_hash(idx);
       },
-      Move::Move(start, dir, range, ref drop_counts, smash) => {
+      Move::Move(start, dir, range, ref drop_counts) => {
         let start_idx = self.idx(start);
         let end_idx = self.offset(start_idx, dir, range);
         let top = self.get_top(end_idx);
@@ -277,19 +307,55 @@ impl Game {
           self.update_hash(idx);
         }
         self.set_top(start_idx, top);
-        if smash { self.set_top(end_idx, Piece::Wall); }
+        if undo.smash { self.set_top(end_idx, Piece::Wall); }
         self.update_hash(start_idx);
       },
     }
   }
 
-  // I would prefer to replace this with an iterator since that's
-  // a bit more general, but that's rather painful to write without
-  // generators here, based on the amount of bookkeeping that would
-  // have to be done. This may also be more efficient than an iterator,
-  // though that's just speculation
+  // All the ways of moving the stack at loc in the given direction, which must be
+  // controlled by the current player
+  pub(crate) fn drop_counts(&self, loc: Loc, dir: Dir) -> TableIter {
+    let idx = self.idx(loc);
+    let mut max_dist = 0;
+    let mut smash = false;
+    let mut l = loc;
+    loop {
+      l = l.offset(dir, 1);
+      if l.x >= self.size as u8 || l.y >= self.size as u8 {
+        break;
+      }
+
+      if self.owners[self.idx(l)].is_empty() {
+        max_dist += 1;
+        continue;
+      }
+      match self.get_top(self.idx(l)) {
+        Piece::Cap => break,
+        Piece::Wall => {
+          smash = self.get_top(idx) == Piece::Cap;
+          if smash { max_dist += 1; }
+          break;
+        },
+        Piece::Flat => {},
+      }
+      max_dist += 1;
+    }
+
+    let mobile_pieces = min(self.rules.carry_limit as usize, self.owners[idx].len());
+    let dist = min(mobile_pieces, max_dist);
+    drop_counts(mobile_pieces, dist, smash)
+  }
+
+  // Lazy version of foreach_move, which generates moves in the same order
+  pub fn moves(&self) -> ::bits::MoveGen {
+    ::bits::MoveGen::new(self)
+  }
+
+  // This may be a little faster than moves() since there's less
+  // bookkeeping between moves, but the iterator is more general
   pub fn foreach_move<E, F: FnMut(Move) -> Result<(),E>>(&self, mut f: F) -> Result<(),E> {
-    if self.round == 1 {
+    if self.opening_swap() {
       for y in 0..self.size {
         for x in 0..self.size {
           let loc = Loc { x: x as u8, y: y as u8};
@@ -308,41 +374,14 @@ impl Game {
             if self.reserves(self.player).count(Piece::Flat) > 0 {
               f(Move::Place(loc, Piece::Flat))?;
               f(Move::Place(loc, Piece::Wall))?;
-            } else if self.reserves(self.player).count(Piece::Cap) > 0 {
+            }
+            if self.reserves(self.player).count(Piece::Cap) > 0 {
               f(Move::Place(loc, Piece::Cap))?;
             }
           } else if self.owners[idx].get(0) == self.player {
             let mut add_moves = |dir: Dir| {
-              let mut max_dist = 0;
-              let mut smash = false;
-              let mut l = loc;
-              loop {
-                l = l.offset(dir, 1);
-                if l.x >= self.size as u8 || l.y >= self.size as u8 {
-                  break;
-                }
-
-                if self.owners[self.idx(l)].is_empty() {
-                  max_dist += 1;
-                  continue;
-                }
-                match self.get_top(self.idx(l)) {
-                  Piece::Cap => break,
-                  Piece::Wall => {
-                    smash = self.get_top(idx) == Piece::Cap;
-                    if smash { max_dist += 1; }
-                    break;
-                  },
-                  Piece::Flat => {},
-                }
-                max_dist += 1;
-              }
-
-              let mobile_pieces = min(self.size, self.owners[idx].len());
-              let dist = min(mobile_pieces, max_dist);
-              use tables::{drop_counts, DropCount};
-              for &DropCount(range, counts) in drop_counts(mobile_pieces, dist, smash) {
-                f(Move::Move(loc, dir, range, counts, false))?;
+              for &DropCount(range, counts) in self.drop_counts(loc, dir) {
+                f(Move::Move(loc, dir, range, counts))?;
               }
               Ok(())
             };
@@ -360,8 +399,73 @@ impl Game {
   }
 
   pub fn status(&self) -> Option<Winner> {
-    let check_road = |p: u64, e1: u64, e2: u64| {
-      let mask = p & !self.walls;
+    // Whoever's road is checked first gets the win if there are roads for both players. By the
+    // dragon clause that's the opponent, since they just moved.
+    let first = if self.rules.dragon_clause { self.player.opponent() } else { self.player };
+    for &p in [first, first.opponent()].iter() {
+      if self.has_road(self.road_pieces(p)) {
+        return Some(Winner::Road(p));
+      }
+    }
+
+    if (self.white | self.black) == self.full_mask
+    || self.white_reserves.empty()
+    || self.black_reserves.empty()
+    {
+      // Compare in half flats so that half point komi works
+      let wcount = (self.white & !self.walls & !self.caps).count_ones() * 2;
+      let bcount = (self.black & !self.walls & !self.caps).count_ones() * 2 + u32::from(self.rules.komi.half_flats());
+      if wcount > bcount { return Some(Winner::Flat(Player::White)); }
+        else if wcount < bcount { return Some(Winner::Flat(Player::Black)); }
+          else { return Some(Winner::Draw); }
+    }
+
+    None
+  }
+
+  // Road pieces (flats and caps on top) for a player
+  #[inline]
+  pub(crate) fn road_pieces(&self, player: Player) -> u64 {
+    let pieces = match player { Player::White => self.white, Player::Black => self.black };
+    pieces & !self.walls
+  }
+
+  #[inline]
+  fn road_edges(&self, edges: RoadEdges) -> (u64, u64) {
+    match edges {
+      RoadEdges::BottomTop => (self.bottom_mask, self.top_mask),
+      RoadEdges::LeftRight => (self.left_mask, self.right_mask),
+    }
+  }
+
+  #[inline]
+  fn loc(&self, idx: usize) -> Loc {
+    Loc { x: (idx % self.size) as u8, y: (idx / self.size) as u8 }
+  }
+
+  fn locs(&self, mut bits: u64) -> Vec<Loc> {
+    let mut locs = Vec::with_capacity(bits.count_ones() as usize);
+    while bits != 0 {
+      locs.push(self.loc(bits.trailing_zeros() as usize));
+      bits &= bits - 1;
+    }
+    locs
+  }
+
+  // Everything in mask connected to seeds
+  #[inline]
+  pub(crate) fn flood(&self, seeds: u64, mask: u64) -> u64 {
+    let mut cur = seeds & mask;
+    loop {
+      let next = self.grow(cur, mask);
+      if next == cur { return cur; }
+      cur = next;
+    }
+  }
+
+  // Whether the road pieces in mask connect either pair of edges
+  fn has_road(&self, mask: u64) -> bool {
+    let check_road = |e1: u64, e2: u64| {
       let mut cur = e1 & mask;
       loop {
         let next = self.grow(cur, mask);
@@ -370,35 +474,152 @@ impl Game {
         cur = next;
       }
     };
+    check_road(self.bottom_mask, self.top_mask) || check_road(self.left_mask, self.right_mask)
+  }
 
-    // Check opponents road first (since they just moved, so if they made roads for both players
-    // they get the win, by the dragon rule
-    let opponent = match self.player { Player::White => self.black, Player::Black => self.white };
-    if check_road(opponent, self.bottom_mask, self.top_mask)
-    || check_road(opponent, self.left_mask, self.right_mask)
-    {
-      return Some(Winner::Road(self.player.opponent()));
+  // Every completed road on the board. A group connecting both pairs of edges is
+  // listed once for each pair. Roads are ordered like the checks in status, so
+  // if both players have a road the winner's comes first.
+  pub fn roads(&self) -> Vec<Road> {
+    let mut roads = Vec::new();
+    let first = if self.rules.dragon_clause { self.player.opponent() } else { self.player };
+    for &player in [first, first.opponent()].iter() {
+      let mask = self.road_pieces(player);
+      for &edges in [RoadEdges::BottomTop, RoadEdges::LeftRight].iter() {
+        let (e1, e2) = self.road_edges(edges);
+        let mut seeds = e1 & mask;
+        while seeds != 0 {
+          let group = self.flood(seeds & seeds.wrapping_neg(), mask);
+          if group & e2 != 0 {
+            roads.push(Road { player, edges, squares: self.locs(group) });
+          }
+          seeds &= !group;
+        }
+      }
     }
+    roads
+  }
 
-    let player = match self.player { Player::White => self.white, Player::Black => self.black };
-    if check_road(player, self.bottom_mask, self.top_mask)
-    || check_road(player, self.left_mask, self.right_mask)
-    {
-      return Some(Winner::Road(self.player));
+  // First road for a player in the order given by roads
+  pub fn road(&self, player: Player) -> Option<Road> {
+    self.roads().into_iter().find(|r| r.player == player)
+  }
+
+  // Road for a player with the fewest squares, as a path from one edge to the
+  // other. Prefers BottomTop roads when both are the same length.
+  pub fn shortest_road(&self, player: Player) -> Option<Road> {
+    let mask = self.road_pieces(player);
+    let mut best : Option<Road> = None;
+    for &edges in [RoadEdges::BottomTop, RoadEdges::LeftRight].iter() {
+      let (e1, e2) = self.road_edges(edges);
+      // layers[i] is every road square within i steps of the first edge
+      let mut layers = vec![e1 & mask];
+      while layers[layers.len() - 1] & e2 == 0 {
+        let cur = layers[layers.len() - 1];
+        let next = self.grow(cur, mask);
+        if next == cur { break; }
+        layers.push(next);
+      }
+      let last = layers[layers.len() - 1];
+      if last & e2 == 0 { continue; }
+      if let Some(ref road) = best {
+        if road.squares.len() <= layers.len() { continue; }
+      }
+
+      // Walk back from the second edge, one layer at a time
+      let mut sq = (last & e2).trailing_zeros() as usize;
+      let mut path = vec![sq];
+      for i in (0 .. layers.len() - 1).rev() {
+        let prev = self.grow(1<<sq, self.full_mask) & layers[i] & !(if i > 0 { layers[i-1] } else { 0 });
+        sq = prev.trailing_zeros() as usize;
+        path.push(sq);
+      }
+      path.reverse();
+      best = Some(Road { player, edges, squares: path.into_iter().map(|idx| self.loc(idx)).collect() });
     }
+    best
+  }
 
-    if (self.white | self.black) == self.full_mask
-    || self.white_reserves.empty()
-    || self.black_reserves.empty()
-    {
-      let wcount = (self.white & !self.walls & !self.caps).count_ones();
-      let bcount = (self.black & !self.walls & !self.caps).count_ones();
-      if wcount > bcount { return Some(Winner::Flat(Player::White)); }
-        else if wcount < bcount { return Some(Winner::Flat(Player::Black)); }
-          else { return Some(Winner::Draw); }
+  // Moves that would win by road for player if it were their turn. Without the
+  // dragon clause, moves that also complete a road for the opponent aren't wins
+  // and aren't included.
+  pub fn road_threats(&self, player: Player) -> Vec<Move> {
+    let mut threats = Vec::new();
+    let _ = self.foreach_road_threat(player, |m| -> Result<(),()> { threats.push(m); Ok(()) });
+    threats
+  }
+
+  // Same as !road_threats(player).is_empty(), but stops at the first threat
+  pub fn has_road_threat(&self, player: Player) -> bool {
+    self.foreach_road_threat(player, |_| Err(())).is_err()
+  }
+
+  // Whether the player who just moved threatens to win by road next turn
+  #[inline]
+  pub fn is_tak(&self) -> bool {
+    self.has_road_threat(self.player.opponent())
+  }
+
+  pub fn foreach_road_threat<E, F: FnMut(Move) -> Result<(),E>>(&self, player: Player, mut f: F) -> Result<(),E> {
+    if self.opening_swap() {
+      return Ok(());
+    }
+    let own = self.road_pieces(player);
+    let opp = self.road_pieces(player.opponent());
+
+    // Placements on empty squares next to groups connected to each edge of a pair
+    let reserves = *self.reserves(player);
+    let pieces = [Piece::Flat, Piece::Cap];
+    if pieces.iter().any(|&p| reserves.count(p) > 0) {
+      let empty = self.full_mask & !(self.white | self.black);
+      let mut spots = 0;
+      for &edges in [RoadEdges::BottomTop, RoadEdges::LeftRight].iter() {
+        let (e1, e2) = self.road_edges(edges);
+        let near1 = self.grow(self.flood(e1, own), self.full_mask) | e1;
+        let near2 = self.grow(self.flood(e2, own), self.full_mask) | e2;
+        spots |= near1 & near2 & empty;
+      }
+      for loc in self.locs(spots) {
+        for &piece in pieces.iter() {
+          if reserves.count(piece) > 0 { f(Move::Place(loc, piece))?; }
+        }
+      }
     }
 
-    None
+    // Stack moves only change the tops along one line, so work out the road
+    // pieces afterwards from the stacks rather than playing each move
+    let controlled = match player { Player::White => self.white, Player::Black => self.black };
+    for idx in 0 .. self.size * self.size {
+      if controlled & (1<<idx) == 0 { continue; }
+      let stack = self.owners[idx];
+      let top = self.get_top(idx);
+      let loc = self.loc(idx);
+      for &dir in [Dir::Up, Dir::Down, Dir::Left, Dir::Right].iter() {
+        for &DropCount(range, counts) in self.drop_counts(loc, dir) {
+          let mut carried = counts.iter().map(|&c| c as usize).sum::<usize>();
+          let mut own = own & !(1<<idx);
+          let mut opp = opp & !(1<<idx);
+          // Whatever is left on the start square is a flat
+          if stack.len() > carried {
+            if stack.get(carried) == player { own |= 1<<idx; } else { opp |= 1<<idx; }
+          }
+          for i in 1 ..= range {
+            let sq = self.offset(idx, dir, i);
+            carried -= counts[(i-1) as usize] as usize;
+            own &= !(1<<sq);
+            opp &= !(1<<sq);
+            if i < range || top != Piece::Wall {
+              if stack.get(carried) == player { own |= 1<<sq; } else { opp |= 1<<sq; }
+            }
+          }
+          if self.has_road(own) && (self.rules.dragon_clause || !self.has_road(opp)) {
+            f(Move::Move(loc, dir, range, counts))?;
+          }
+        }
+      }
+    }
+
+    Ok(())
   }
 
   #[inline]
@@ -412,66 +633,303 @@ impl Game {
       Player::Black => &self.black_reserves,
     }
   }
+  #[inline]
+  pub fn size(&self) -> usize { self.size }
+  #[inline]
+  pub fn rules(&self) -> &Rules { &self.rules }
+  #[inline]
+  pub fn komi(&self) -> Komi { self.rules.komi }
+  pub fn set_komi(&mut self, komi: Komi) { self.rules.komi = komi; }
+
+  // Whether players are placing their opponent's first stone
+  #[inline]
+  pub(crate) fn opening_swap(&self) -> bool {
+    self.round == 1 && self.rules.swap
+  }
+  #[inline]
+  pub fn stack(&self, loc: Loc) -> &::bits::Stack { &self.owners[self.idx(loc)] }
+
+  // Type of the top piece of the stack at loc, or None if the square is empty
+  #[inline]
+  pub fn top(&self, loc: Loc) -> Option<Piece> {
+    let idx = self.idx(loc);
+    if self.owners[idx].is_empty() { None } else { Some(self.get_top(idx)) }
+  }
+
+  // Setters used when building a game from a position (i.e. TPS) rather than by
+  // playing moves. Nothing is validated here, callers are expected to keep the
+  // reserves consistent with the pieces on the board.
+  pub(crate) fn set_square(&mut self, loc: Loc, stack: ::bits::Stack, top: Piece) {
+    let idx = self.idx(loc);
+    self.update_hash(idx);
+    self.owners[idx] = stack;
+    self.fix_top(idx);
+    if !stack.is_empty() { self.set_top(idx, top); }
+    self.update_hash(idx);
+  }
+
+  pub(crate) fn set_reserves(&mut self, player: Player, reserves: Reserves) {
+    *self.reserves_mut(player) = reserves;
+  }
+
+  pub(crate) fn set_turn(&mut self, player: Player, round: u32) {
+    self.player = player;
+    self.round = round;
+  }
 }
 
 impl ToString for Game {
   fn to_string(&self) -> String {
-    let mut out = String::new();
-    for j in (0 .. self.size).rev() {
-      let mut prev_empty = 0;
-      for i in 0 .. self.size {
-        let idx = self.idx(Loc { x: i as u8, y: j as u8 });
-        if self.owners[idx].is_empty() {
-          prev_empty += 1;
-          if i == (self.size - 1) {
-            out.push('x');
-            if prev_empty > 1 { out.push_str(prev_empty.to_string().as_str()); }
-          }
-        } else {
-          if prev_empty > 0 {
-            out.push('x');
-            if prev_empty > 1 { out.push_str(prev_empty.to_string().as_str()); }
-            out.push(',');
-            prev_empty = 0;
-          }
-          for p in self.owners[idx].into_iter().rev() {
-            match p {
-              Player::White => out.push('1'),
-              Player::Black => out.push('2'),
+    ::tps::to_string(self)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use test::Bencher;
+  use std::collections::HashSet;
+  use game::{self, Dir, Komi, Loc, Move, Piece, Player, Road, RoadEdges, Rules, Winner};
+  use tps;
+
+  #[test]
+  fn custom_rules() {
+    assert!(game::Game::with_rules(Rules { size: 9, ..Rules::new(8).unwrap() }).is_none());
+    assert!(game::Game::with_rules(Rules { carry_limit: 6, ..Rules::new(5).unwrap() }).is_none());
+    assert!(game::Game::with_rules(Rules { flats: 0, ..Rules::new(5).unwrap() }).is_none());
+
+    let g = game::Game::with_rules(Rules { caps: 2, ..Rules::new(6).unwrap() }).unwrap();
+    assert_eq!(g.reserves(Player::White).count(Piece::Cap), 2);
+
+    // Without the swap, the first player places their own stone and may place any type
+    let mut g = game::Game::with_rules(Rules { swap: false, ..Rules::new(5).unwrap() }).unwrap();
+    assert_eq!(g.moves().count(), 25 * 3);
+    let m = Move::Place(Loc { x: 0, y: 0 }, Piece::Cap);
+    assert_eq!(g.validate(&m), game::MoveValidity::Valid);
+    let undo = g.execute(&m);
+    assert_eq!(g.stack(Loc { x: 0, y: 0 }).get(0), Player::White);
+    assert_eq!(g.reserves(Player::White).count(Piece::Cap), 0);
+    g.undo(&undo);
+    assert_eq!(g.reserves(Player::White).count(Piece::Cap), 1);
+  }
+
+  #[test]
+  fn validate_matches_movegen() {
+    // Every stack move with a legal shape, including ones that run off the
+    // board or wrap onto the next row
+    fn drops(left: u8, prefix: &mut Vec<u8>, out: &mut Vec<Vec<u8>>) {
+      if !prefix.is_empty() { out.push(prefix.clone()); }
+      for d in 1 ..= left {
+        prefix.push(d);
+        drops(left - d, prefix, out);
+        prefix.pop();
+      }
+    }
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut rand = move |n: usize| {
+      seed ^= seed << 13; seed ^= seed >> 7; seed ^= seed << 17;
+      (seed % n as u64) as usize
+    };
+    for size in 3 .. 6 {
+      let mut shapes = Vec::new();
+      drops(size as u8, &mut Vec::new(), &mut shapes);
+      let mut g = game::new(size).unwrap();
+      while g.status().is_none() {
+        let legal : HashSet<Move> = g.moves().collect();
+        for y in 0 .. size as u8 {
+          for x in 0 .. size as u8 {
+            for &dir in [Dir::Up, Dir::Down, Dir::Left, Dir::Right].iter() {
+              for shape in shapes.iter() {
+                let mut counts = [0u8; 7];
+                counts[.. shape.len()].copy_from_slice(shape);
+                let m = Move::Move(Loc { x, y }, dir, shape.len() as u8, counts);
+                assert_eq!(g.validate(&m) == game::MoveValidity::Valid, legal.contains(&m), "{:?} in {}", m, g.to_string());
+              }
             }
           }
-          match self.get_top(idx) {
-            Piece::Cap => out.push('C'),
-            Piece::Wall => out.push('S'),
-            _ => {},
-          }
-          if i < (self.size - 1) {
-            out.push(',');
-          }
         }
-
+        let legal : Vec<Move> = legal.into_iter().collect();
+        g.execute(&legal[rand(legal.len())]);
       }
-      if j > 0 {
-        out.push('/');
+    }
+
+    let g = tps::parse("x5/x5/x5/x5/x3,1,1 1 5").unwrap();
+    assert_eq!(g.validate(&Move::Move(Loc { x: 4, y: 0 }, Dir::Right, 1, [1,0,0,0,0,0,0])), game::MoveValidity::EndOutOfBounds);
+    assert_eq!(g.validate(&Move::Move(Loc { x: 4, y: 0 }, Dir::Down, 1, [1,0,0,0,0,0,0])), game::MoveValidity::EndOutOfBounds);
+    assert_eq!(g.validate(&Move::Move(Loc { x: 4, y: 0 }, Dir::Up, 0, [0; 7])), game::MoveValidity::InvalidDropCounts);
+    assert_eq!(g.validate(&Move::Move(Loc { x: 3, y: 0 }, Dir::Up, 2, [1,0,0,0,0,0,0])), game::MoveValidity::InvalidDropCounts);
+  }
+
+  #[test]
+  fn carry_limit() {
+    let is_stack_move = |m: &Move| match *m { Move::Move(..) => true, _ => false };
+    let m = Move::Move(Loc { x: 0, y: 0 }, Dir::Up, 1, [3,0,0,0,0,0,0]);
+
+    let g = tps::parse("x5/x5/x5/x5/1111,x4 1 5").unwrap();
+    assert_eq!(g.moves().filter(&is_stack_move).count(), 2 * 15);
+    assert_eq!(g.validate(&m), game::MoveValidity::Valid);
+
+    let rules = Rules { carry_limit: 2, ..Rules::new(5).unwrap() };
+    let g = tps::parse_with_rules("x5/x5/x5/x5/1111,x4 1 5", rules).unwrap();
+    assert_eq!(g.moves().filter(&is_stack_move).count(), 2 * 3);
+    assert_eq!(g.validate(&m), game::MoveValidity::CarryLimit);
+  }
+
+  #[test]
+  fn hash_collision() {
+    // These used to hash the same, since the stacks on c2 and c3 only differ in size
+    let a = tps::parse("1S,22,1,21S/1S,x,12,1S/1S,2S,1,11S/21,x2,2S 1 17").unwrap();
+    let b = tps::parse("1S,22,1,21S/1S,x,112,1S/1S,2S,x,11S/21,x2,2S 1 17").unwrap();
+    assert!(a.hash() != b.hash());
+  }
+
+  #[test]
+  fn undo_smash() {
+    let m = Move::Move(Loc { x: 0, y: 0 }, Dir::Right, 1, [1,0,0,0,0,0,0]);
+    let mut g = tps::parse("x5/x5/x5/x5/1C,2S,x3 1 3").unwrap();
+    let hash = g.hash();
+    let undo = g.execute(&m);
+    assert_eq!(undo.m, m);
+    assert_eq!(g.top(Loc { x: 1, y: 0 }), Some(Piece::Cap));
+    g.undo(&undo);
+    assert_eq!(g.to_string(), "x5/x5/x5/x5/1C,2S,x3 1 3");
+    assert_eq!(g.hash(), hash);
+
+    // Moves no longer change when played, so they can be used as keys
+    let g = game::new(5).unwrap();
+    let moves : HashSet<Move> = g.moves().collect();
+    assert_eq!(moves.len(), 25);
+  }
+
+  #[test]
+  fn roads() {
+    let loc = |s: &str| Loc { x: s.as_bytes()[0] - b'a', y: s.as_bytes()[1] - b'1' };
+    let locs = |s: &str| s.split(' ').map(&loc).collect::<Vec<Loc>>();
+
+    // White road from a1 to e1 with a branch off of c1, and no road for black
+    let g = tps::parse("x5/x2,2,x2/x,2S,1,2,x/x2,1,x2/1,1,1C,1,1 2 8").unwrap();
+    assert_eq!(g.status(), Some(Winner::Road(Player::White)));
+    let roads = g.roads();
+    assert_eq!(roads.len(), 1);
+    assert_eq!(roads[0], Road { player: Player::White, edges: RoadEdges::LeftRight, squares: locs("a1 b1 c1 d1 e1 c2 c3") });
+    assert_eq!(g.road(Player::Black), None);
+    let shortest = g.shortest_road(Player::White).unwrap();
+    assert_eq!(shortest.squares, locs("a1 b1 c1 d1 e1"));
+
+    // Winding road, where the shortest path skips the detour through b3
+    let g = tps::parse("x,1,x3/x,1,x3/1,1,x3/1,1,x3/x,1,x3 2 8").unwrap();
+    let road = g.road(Player::White).unwrap();
+    assert_eq!((road.edges, road.squares.len()), (RoadEdges::BottomTop, 7));
+    assert_eq!(g.shortest_road(Player::White).unwrap().squares, locs("b1 b2 b3 b4 b5"));
+    assert_eq!(g.shortest_road(Player::Black), None);
+
+    // Both players get a road from b2-, and by the dragon clause the mover wins
+    let mut g = tps::parse("x3/2,21,2/1,x,1 1 5").unwrap();
+    g.execute(&Move::Move(Loc { x: 1, y: 1 }, Dir::Down, 1, [1,0,0,0,0,0,0]));
+    let roads = g.roads();
+    assert_eq!(roads.len(), 2);
+    assert_eq!((roads[0].player, roads[0].edges), (Player::White, RoadEdges::LeftRight));
+    assert_eq!((roads[1].player, &roads[1].squares[..]), (Player::Black, &locs("a2 b2 c2")[..]));
+  }
+
+  #[test]
+  fn road_threats() {
+    // White threatens c1 and c1 can't be blocked by placing, so black is in Tak
+    let g = tps::parse("x5/x5/x5/2,2,x3/1,1,x,1,1 2 3").unwrap();
+    assert!(g.is_tak());
+    assert_eq!(g.road_threats(Player::White), vec![Move::Place(Loc { x: 2, y: 0 }, Piece::Flat), Move::Place(Loc { x: 2, y: 0 }, Piece::Cap)]);
+    assert!(!g.has_road_threat(Player::Black));
+
+    // Moving two from b2 onto c2 keeps white on top of both, which completes a road
+    let g = tps::parse("x4/x4/1,2121,x,2/x4 1 6").unwrap();
+    assert_eq!(g.road_threats(Player::White), vec![]);
+    let g = tps::parse("x4/x4/1,2121,x,1/x4 1 6").unwrap();
+    assert_eq!(g.road_threats(Player::White), vec![
+      Move::Place(Loc { x: 2, y: 1 }, Piece::Flat),
+      Move::Move(Loc { x: 1, y: 1 }, Dir::Right, 1, [2,0,0,0,0,0,0]),
+    ]);
+  }
+
+  #[test]
+  fn road_threats_match_brute_force() {
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut rand = move |n: usize| {
+      seed ^= seed << 13; seed ^= seed >> 7; seed ^= seed << 17;
+      (seed % n as u64) as usize
+    };
+    let key = |m: &Move| format!("{:?}", m);
+    let mut threats_seen = 0;
+    for game_num in 0 .. 60 {
+      let size = 3 + game_num % 4;
+      let rules = Rules { dragon_clause: game_num % 3 != 0, ..Rules::new(size).unwrap() };
+      let mut g = game::Game::with_rules(rules).unwrap();
+      while g.status().is_none() {
+        let player = g.cur_player();
+        let moves : Vec<Move> = g.moves().collect();
+        let mut expected : Vec<Move> = moves.iter().cloned().filter(|m| {
+          let mut next = g.clone();
+          next.execute(m);
+          next.status() == Some(Winner::Road(player))
+        }).collect();
+        let mut threats = g.road_threats(player);
+        expected.sort_by_key(&key);
+        threats.sort_by_key(&key);
+        assert_eq!(threats, expected, "{}", g.to_string());
+        assert_eq!(g.has_road_threat(player), !threats.is_empty());
+        threats_seen += threats.len();
+
+        // Mostly avoid winning straight away, to get to busier positions
+        let quiet : Vec<&Move> = moves.iter().filter(|m| !expected.contains(m)).collect();
+        if quiet.is_empty() || rand(8) == 0 {
+          g.execute(&moves[rand(moves.len())]);
+        } else {
+          g.execute(quiet[rand(quiet.len())]);
+        }
       }
     }
+    assert!(threats_seen > 100);
+  }
 
-    out.push(' ');
-    out.push(match self.player { Player::White => '1', Player::Black => '2' });
-    out.push(' ');
-    out.push_str(self.round.to_string().as_str());
-    out
+  #[test]
+  fn dragon_clause() {
+    // b2- completes a road on the first rank for white, and uncovers black's road on the second
+    let m = Move::Move(Loc { x: 1, y: 1 }, Dir::Down, 1, [1,0,0,0,0,0,0]);
+    let mut g = tps::parse("x3/2,21,2/1,x,1 1 5").unwrap();
+    g.execute(&m);
+    assert_eq!(g.status(), Some(Winner::Road(Player::White)));
+
+    let mut g = tps::parse_with_rules("x3/2,21,2/1,x,1 1 5", Rules { dragon_clause: false, ..Rules::new(3).unwrap() }).unwrap();
+    g.execute(&m);
+    assert_eq!(g.status(), Some(Winner::Road(Player::Black)));
   }
-}
 
-#[cfg(test)]
-mod test {
-  use test::Bencher;
+  #[test]
+  fn flat_win_komi() {
+    // Full board, white has 5 flats to black's 4
+    let mut g = tps::parse("1,2,1/2,1,2/1,2,1 1 6").unwrap();
+    assert_eq!(g.status(), Some(Winner::Flat(Player::White)));
+    g.set_komi(Komi::from_half_flats(1));
+    assert_eq!(g.status(), Some(Winner::Flat(Player::White)));
+    g.set_komi(Komi::from_half_flats(2));
+    assert_eq!(g.status(), Some(Winner::Draw));
+    g.set_komi(Komi::from_half_flats(3));
+    assert_eq!(g.status(), Some(Winner::Flat(Player::Black)));
+  }
+
+  #[test]
+  fn komi_from_str() {
+    assert_eq!("0".parse::<Komi>(), Ok(Komi::from_half_flats(0)));
+    assert_eq!("2.5".parse::<Komi>(), Ok(Komi::from_half_flats(5)));
+    assert_eq!("3.0".parse::<Komi>(), Ok(Komi::from_half_flats(6)));
+    assert!("2.25".parse::<Komi>().is_err());
+    assert!("-1".parse::<Komi>().is_err());
+    assert_eq!(Komi::from_half_flats(5).to_string(), "2.5");
+    assert_eq!(Komi::from_half_flats(4).to_string(), "2");
+  }
 
   #[bench]
   fn clone_game(b: &mut Bencher) {
-    let mut m = ::game::Move::Place(::game::Loc{x:0,y:1}, ::game::Piece::Flat);
-    let mut g : ::game::Game = ::game::new(5).unwrap();
+    let m = ::game::Move::Place(::game::Loc{x:0,y:1}, ::game::Piece::Flat);
+    let g : ::game::Game = ::game::new(5).unwrap();
     assert_eq!(g.validate(&m), ::game::MoveValidity::Valid);
     b.iter(|| {
       g.clone()
@@ -480,38 +938,38 @@ mod test {
 
   #[bench]
   fn make_move(b: &mut Bencher) {
-    let mut m = ::game::Move::Place(::game::Loc{x:0,y:1}, ::game::Piece::Flat);
-    let mut g : ::game::Game = ::game::new(5).unwrap();
+    let m = ::game::Move::Place(::game::Loc{x:0,y:1}, ::game::Piece::Flat);
+    let g : ::game::Game = ::game::new(5).unwrap();
     assert_eq!(g.validate(&m), ::game::MoveValidity::Valid);
     b.iter(|| {
       let mut g1 = g.clone();
-      g1.execute(&mut m);
+      g1.execute(&m);
       g1
     });
   }
 
   #[bench]
   fn check_status(b: &mut Bencher) {
-    let mut m = ::game::Move::Place(::game::Loc{x:0,y:1}, ::game::Piece::Flat);
-    let mut g : ::game::Game = ::game::new(5).unwrap();
+    let m = ::game::Move::Place(::game::Loc{x:0,y:1}, ::game::Piece::Flat);
+    let g : ::game::Game = ::game::new(5).unwrap();
     assert_eq!(g.validate(&m), ::game::MoveValidity::Valid);
     b.iter(|| {
       let mut g1 = g.clone();
-      g1.execute(&mut m);
+      g1.execute(&m);
       g1.status()
     });
   }
 
   #[bench]
   fn whole_shebang(b: &mut Bencher) {
-    let mut m = ::game::Move::Place(::game::Loc{x:0,y:1}, ::game::Piece::Flat);
-    let mut g : ::game::Game = ::game::new(5).unwrap();
+    let m = ::game::Move::Place(::game::Loc{x:0,y:1}, ::game::Piece::Flat);
+    let g : ::game::Game = ::game::new(5).unwrap();
     assert_eq!(g.validate(&m), ::game::MoveValidity::Valid);
     b.iter(|| {
       let mut g1 = g.clone();
-      g1.execute(&mut m);
+      let undo = g1.execute(&m);
       let status = g.status();
-      g1.undo(&m);
+      g1.undo(&undo);
       status
     });
   }
@@ -4,6 +4,17 @@ pub use self::game::Game;
 mod stack;
 pub use self::stack::*;
 
+mod movegen;
+pub use self::movegen::MoveGen;
+
+mod perft;
+pub use self::perft::PERFT_SUITE;
+
+mod symmetry;
+
+mod board;
+pub use self::board::Board;
+
 #[cfg(feature = "stack_128")]
 type StackRepr = u128;
 
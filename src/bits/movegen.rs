@@ -0,0 +1,128 @@
+use game::{Dir, Loc, Move, Piece};
+use tables::{DropCount, TableIter};
+use bits::Game;
+
+static DIRS : [Dir; 4] = [Dir::Up, Dir::Down, Dir::Left, Dir::Right];
+
+#[derive(Clone,Copy)]
+enum Step {
+  // Nothing generated yet for the current square
+  Square,
+  // Flat was placed, wall comes next
+  Wall,
+  // Flat and wall (if any) were placed, cap comes next
+  Cap,
+  // Start generating stack moves in DIRS[n]
+  Dir(usize),
+  // Stack moves in DIRS[n] still to be returned
+  Drops(usize, TableIter),
+}
+
+// Iterator over the legal moves in a position, in the same order as
+// Game::foreach_move. Nothing is allocated while iterating.
+#[derive(Clone)]
+pub struct MoveGen<'a> {
+  game: &'a Game,
+  idx: usize,
+  step: Step,
+}
+
+impl<'a> MoveGen<'a> {
+  pub fn new(game: &'a Game) -> Self {
+    MoveGen { game, idx: 0, step: Step::Square }
+  }
+
+  #[inline]
+  fn next_square(&mut self) {
+    self.idx += 1;
+    self.step = Step::Square;
+  }
+}
+
+impl<'a> Iterator for MoveGen<'a> {
+  type Item = Move;
+
+  fn next(&mut self) -> Option<Move> {
+    let g = self.game;
+    let size = g.size();
+    let player = g.cur_player();
+    loop {
+      if self.idx >= size * size {
+        return None;
+      }
+      let loc = Loc { x: (self.idx % size) as u8, y: (self.idx / size) as u8 };
+
+      match self.step {
+        Step::Square => {
+          let stack = g.stack(loc);
+          if stack.is_empty() {
+            if g.opening_swap() {
+              self.next_square();
+              return Some(Move::Place(loc, Piece::Flat));
+            } else if g.reserves(player).count(Piece::Flat) > 0 {
+              self.step = Step::Wall;
+              return Some(Move::Place(loc, Piece::Flat));
+            }
+            self.step = Step::Cap;
+          } else if !g.opening_swap() && stack.get(0) == player {
+            self.step = Step::Dir(0);
+          } else {
+            self.next_square();
+          }
+        },
+        Step::Wall => {
+          self.step = Step::Cap;
+          return Some(Move::Place(loc, Piece::Wall));
+        },
+        Step::Cap => {
+          self.next_square();
+          if g.reserves(player).count(Piece::Cap) > 0 {
+            return Some(Move::Place(loc, Piece::Cap));
+          }
+        },
+        Step::Dir(n) => {
+          if n < DIRS.len() {
+            self.step = Step::Drops(n, g.drop_counts(loc, DIRS[n]));
+          } else {
+            self.next_square();
+          }
+        },
+        Step::Drops(n, mut drops) => {
+          match drops.next() {
+            Some(&DropCount(range, counts)) => {
+              self.step = Step::Drops(n, drops);
+              return Some(Move::Move(loc, DIRS[n], range, counts));
+            },
+            None => self.step = Step::Dir(n + 1),
+          }
+        },
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use game::{self, Move};
+  use tps;
+
+  #[test]
+  fn matches_foreach_move() {
+    let positions = [
+      "x3/x3/x3 1 1",
+      "x5/x5/x2,12C,x2/x5/2,x4 1 3",
+      "2,x5/x6/x2,1S,x3/x,221C,x4/x6/1,x4,2 2 6",
+      "x2,2,x5/x8/x3,1112C,x4/x8/x8/x3,21S,x4/x8/x7,1 1 9",
+    ];
+    for position in positions.iter() {
+      let g = tps::parse(position).unwrap();
+      let mut expected = Vec::new();
+      g.foreach_move(|m| -> Result<(),()> { expected.push(m); Ok(()) }).unwrap();
+      let generated : Vec<Move> = g.moves().collect();
+      assert_eq!(generated, expected);
+    }
+
+    let g = game::new(5).unwrap();
+    assert_eq!(g.moves().take(3).count(), 3);
+  }
+}
@@ -0,0 +1,105 @@
+use game::Move;
+use bits::Game;
+
+// Positions with known perft node counts, as (TPS, counts for depths 1, 2, ...).
+// There are three positions for each size: the start position and two midgame
+// positions with walls, capstones and tall stacks. Positions where the game is
+// over have no children, so they only count as leaf nodes.
+pub static PERFT_SUITE : [(&str, &[u64]); 18] = [
+  ("x3/x3/x3 1 1", &[9, 72, 1200, 17792, 271812]),
+  ("1S,2S,x/111S,2,1/x2,122S 2 10", &[22, 330, 4810, 64162, 800343]),
+  ("1,2S,2/x,2,x/2S,1121S,12S 1 11", &[8, 97, 949, 11037, 110883]),
+  ("x4/x4/x4/x4 1 1", &[16, 240, 7440, 216464]),
+  ("2,1,2S,x/1S,1S,2,112/x,2S,1S,2S/2,1,1S,x 2 14", &[23, 354, 7510, 129521, 2690307]),
+  ("x,12,1S,1S/x,1S,2,2S/212S,x,1S,1S/2S,22S,11S,1S 2 14", &[20, 246, 3696, 43036, 613012]),
+  ("x5/x5/x5/x5/x5 1 1", &[25, 600, 43320, 2999784]),
+  ("1C,x2,111,x/x3,2S,2/1S,2S,2C,x2/212S,2S,1,x,12S/2,1S,2S,1,2S 2 17", &[42, 1363, 56304, 1772221, 72314072]),
+  ("x,2C,1,x,211C/2S,1S,1S,1S,1/2S,12,22S,x2/x,2,x,1,x/12S,2S,1,1,x 1 17", &[45, 1717, 71682, 2755361, 111607483]),
+  ("x6/x6/x6/x6/x6/x6 1 1", &[36, 1260, 132720, 13586048]),
+  ("1C,x2,1,2,2C/2,x2,1S,x2/x,2S,121S,1S,x2/1S,1S,x,1S,x,2/2S,x2,1S,x,2/x,1,2S,2,x,12 1 15", &[62, 3519, 209426, 11739698, 681646797]),
+  ("x,2S,x,21,x,212C/1S,x,2S,1S,x,1/x,1,2S,x,2S,1C/x,1,22S,x,1S,1S/2S,x,1S,x2,1/2,1S,1S,2S,1,2 2 20", &[55, 2931, 159505, 8323400, 449630527]),
+  ("x7/x7/x7/x7/x7/x7/x7 1 1", &[49, 2352, 339696, 48051008]),
+  ("x,1,1S,x,1,2,2/2C,x,1,1S,x3/x2,1,x2,1,x/2C,x2,1C,x2,22/2S,x,1,x4/1S,1S,x,12S,2,x2/x2,121C,1S,2S,x,1S 2 20", &[84, 8417, 697789, 67924637]),
+  ("212S,x,2,x,2,2S,1S/x4,1,1S,x/2S,2C,x3,2C,1/x,2,x2,2,x,1S/2S,x,1C,x,1S,x,1/2S,x2,112S,x2,2/2,x2,1,11C,x2 2 20", &[113, 8986, 958828, 76196818]),
+  ("x8/x8/x8/x8/x8/x8/x8/x8 1 1", &[64, 4032, 764064, 142512336]),
+  ("2,x,1S,x,1,x2,2/2,x6,211/x,1,1S,1,1,2C,x2/21C,x,2S,x5/x2,1C,1,x2,2,x/1S,x,1,x2,1S,x,2C/x,1,11,2,x,1,x2/x2,2S,2,x3,2 2 23", &[105, 15515, 1633584, 237540308]),
+  ("2,1C,1S,x,1,1C,x2/x2,2S,1,x,2C,x2/x2,2,x,2S,12S,x2/1S,x4,1S,1S,2S/x3,1,211S,1S,x,1/x,2,1S,2,x,2,x2/x,2,x3,2S,1S,x/1,1,x,2C,1,2S,x2 1 23", &[120, 12218, 1445698, 147144216]),
+];
+
+impl Game {
+  // Number of leaf nodes in the move tree `depth` plies deep
+  pub fn perft(&mut self, depth: u32) -> u64 {
+    if depth == 0 {
+      return 1;
+    }
+    if self.status().is_some() {
+      return 0;
+    }
+
+    let mut moves = Vec::new();
+    let _ = self.foreach_move(|m| -> Result<(),()> { moves.push(m); Ok(()) });
+    if depth == 1 {
+      return moves.len() as u64;
+    }
+
+    let mut nodes = 0;
+    for m in moves {
+      let undo = self.execute(&m);
+      nodes += self.perft(depth - 1);
+      self.undo(&undo);
+    }
+    nodes
+  }
+
+  // Perft split up by the first move, which helps narrow down generator bugs
+  pub fn perft_divide(&mut self, depth: u32) -> Vec<(Move, u64)> {
+    let mut moves = Vec::new();
+    if depth > 0 && self.status().is_none() {
+      let _ = self.foreach_move(|m| -> Result<(),()> { moves.push(m); Ok(()) });
+    }
+
+    moves.into_iter().map(|m| {
+      let undo = self.execute(&m);
+      let nodes = self.perft(depth - 1);
+      self.undo(&undo);
+      (m, nodes)
+    }).collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::PERFT_SUITE;
+  use tps;
+
+  fn check_suite(max_nodes: u64) {
+    for &(position, counts) in PERFT_SUITE.iter() {
+      let mut g = tps::parse(position).unwrap();
+      for (depth, &count) in counts.iter().enumerate() {
+        if count > max_nodes { break; }
+        assert_eq!(g.perft(depth as u32 + 1), count, "perft({}) of {}", depth + 1, position);
+      }
+      assert_eq!(g.to_string(), position);
+    }
+  }
+
+  #[test]
+  fn perft_suite() {
+    check_suite(1_000_000);
+  }
+
+  #[test]
+  #[ignore]
+  fn perft_suite_full() {
+    check_suite(u64::max_value());
+  }
+
+  #[test]
+  fn divide_sums_to_perft() {
+    let (position, counts) = PERFT_SUITE[4];
+    let mut g = tps::parse(position).unwrap();
+    let divided = g.perft_divide(3);
+    assert_eq!(divided.len() as u64, counts[0]);
+    assert_eq!(divided.iter().map(|&(_, n)| n).sum::<u64>(), counts[2]);
+  }
+}
@@ -1,8 +1,12 @@
 use game::Player;
 use std::iter::{FromIterator,IntoIterator};
 use std::hash::{Hash,Hasher};
+use std::mem;
 use bits::BinConv;
 
+// Tallest stack that fits in the bits of a Stack
+pub const MAX_HEIGHT : usize = 8 * mem::size_of::<::bits::StackRepr>();
+
 #[derive(Debug,Clone,Copy)]
 pub struct Stack {
   owners: ::bits::StackRepr,
@@ -0,0 +1,114 @@
+use game::{Loc, Symmetry, SYMMETRIES};
+use bits::Game;
+
+impl Game {
+  // Copy of the game with the board rotated or reflected by sym. Reserves, turn
+  // and rules are unchanged.
+  pub fn transform(&self, sym: Symmetry) -> Game {
+    let mut g = self.clone();
+    if sym == Symmetry::Identity {
+      return g;
+    }
+    let size = self.size();
+    for y in 0 .. size as u8 {
+      for x in 0 .. size as u8 {
+        let loc = Loc { x, y };
+        let top = self.top(loc).unwrap_or(::game::Piece::Flat);
+        g.set_square(loc.transform(sym, size), *self.stack(loc), top);
+      }
+    }
+    g
+  }
+
+  // Smallest hash of the eight symmetric positions, along with the transform
+  // that takes this position to the one with that hash. Positions that are
+  // rotations or reflections of each other have the same canonical hash.
+  pub fn canonical_hash(&self) -> (u64, Symmetry) {
+    let mut best = (self.hash(), Symmetry::Identity);
+    for &sym in SYMMETRIES[1..].iter() {
+      let hash = self.transform(sym).hash();
+      if hash < best.0 {
+        best = (hash, sym);
+      }
+    }
+    best
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use game::{self, Dir, Loc, Move, Piece, Symmetry, SYMMETRIES};
+  use tps;
+
+  #[test]
+  fn transform_moves() {
+    let a1 = Loc { x: 0, y: 0 };
+    let b1 = Loc { x: 1, y: 0 };
+    let expected = [
+      (Symmetry::Identity, "a1", "b1", Dir::Up),
+      (Symmetry::Rotate90, "a5", "a4", Dir::Right),
+      (Symmetry::Rotate180, "e5", "d5", Dir::Down),
+      (Symmetry::Rotate270, "e1", "e2", Dir::Left),
+      (Symmetry::FlipHorizontal, "e1", "d1", Dir::Up),
+      (Symmetry::FlipVertical, "a5", "b5", Dir::Down),
+      (Symmetry::Transpose, "a1", "a2", Dir::Right),
+      (Symmetry::AntiTranspose, "e5", "e4", Dir::Left),
+    ];
+    let name = |loc: Loc| format!("{}{}", (loc.x + b'a') as char, loc.y + 1);
+    for &(sym, a1_to, b1_to, up_to) in expected.iter() {
+      assert_eq!(name(a1.transform(sym, 5)), a1_to, "{:?}", sym);
+      assert_eq!(name(b1.transform(sym, 5)), b1_to, "{:?}", sym);
+      assert_eq!(Dir::Up.transform(sym), up_to, "{:?}", sym);
+    }
+
+    let m = Move::Move(Loc { x: 1, y: 2 }, Dir::Right, 2, [2,1,0,0,0,0,0]);
+    assert_eq!(m.transform(Symmetry::Rotate90, 6), Move::Move(Loc { x: 2, y: 4 }, Dir::Down, 2, [2,1,0,0,0,0,0]));
+    for &sym in SYMMETRIES.iter() {
+      assert_eq!(m.transform(sym, 6).transform(sym.inverse(), 6), m);
+      let place = Move::Place(Loc { x: 3, y: 0 }, Piece::Wall);
+      assert_eq!(place.transform(sym, 4).transform(sym.inverse(), 4), place);
+    }
+  }
+
+  #[test]
+  fn transform_game() {
+    let position = "x2,1,x3/x,2S,x4/x2,12C,x,2,x/x6/1,x5/x4,2,21S 2 6";
+    let g = tps::parse(position).unwrap();
+    let mut hashes = Vec::new();
+    for &sym in SYMMETRIES.iter() {
+      let t = g.transform(sym);
+      assert_eq!(t.transform(sym.inverse()).to_string(), position);
+      // The transformed position has exactly the transformed moves
+      let mut moves : Vec<Move> = g.moves().map(|m| m.transform(sym, 6)).collect();
+      let mut expected : Vec<Move> = t.moves().collect();
+      moves.sort_by_key(|m| format!("{:?}", m));
+      expected.sort_by_key(|m| format!("{:?}", m));
+      assert_eq!(moves, expected);
+      // And its hash matches one computed from scratch
+      assert_eq!(tps::parse(&t.to_string()).unwrap().hash(), t.hash());
+      hashes.push(t.hash());
+    }
+    hashes.sort();
+    hashes.dedup();
+    assert_eq!(hashes.len(), 8);
+    assert_eq!(g.transform(Symmetry::Rotate90).to_string(), "x,1,x4/x4,2S,x/x3,12C,x,1/x6/2,x2,2,x2/21S,x5 2 6");
+  }
+
+  #[test]
+  fn canonical_hash() {
+    let g = tps::parse("x2,1,x3/x,2S,x4/x2,12C,x,2,x/x6/1,x5/x4,2,21S 2 6").unwrap();
+    let (hash, sym) = g.canonical_hash();
+    assert_eq!(g.transform(sym).hash(), hash);
+    for &other in SYMMETRIES.iter() {
+      assert_eq!(g.transform(other).canonical_hash().0, hash);
+    }
+
+    // Symmetric openings fold together
+    let a1 = tps::parse("x5/x5/x5/x5/2,x4 2 1").unwrap();
+    let e5 = tps::parse("x4,2/x5/x5/x5/x5 2 1").unwrap();
+    let c3 = tps::parse("x5/x5/x2,2,x2/x5/x5 2 1").unwrap();
+    assert_eq!(a1.canonical_hash().0, e5.canonical_hash().0);
+    assert!(a1.canonical_hash().0 != c3.canonical_hash().0);
+    assert_eq!(game::new(5).unwrap().canonical_hash().1, Symmetry::Identity);
+  }
+}
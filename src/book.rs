@@ -0,0 +1,441 @@
+// Opening book: how often each move was played from each position in a
+// collection of games, and how those games turned out. Positions that are
+// rotations or reflections of each other share an entry, keyed by
+// Game::canonical_hash, so a1 and e5 openings count together.
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use game::{Dir, Game, Loc, Move, MoveValidity, Piece, Symmetry, Winner, SYMMETRIES};
+use ptn::Ptn;
+#[cfg(feature = "sqlite")]
+use archive::Record;
+
+// Results of the games a move was played in, from the point of view of the
+// player who made it
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub struct Stats {
+  pub count: u32,
+  pub wins: u32,
+  pub draws: u32,
+  pub losses: u32,
+}
+
+impl Stats {
+  // Fraction of the points won, counting draws as half
+  pub fn score(&self) -> f64 {
+    if self.count == 0 {
+      return 0.5;
+    }
+    (f64::from(self.wins) + f64::from(self.draws) / 2.0) / f64::from(self.count)
+  }
+
+  fn add(&mut self, result: Option<bool>) {
+    self.count += 1;
+    match result {
+      Some(true) => self.wins += 1,
+      Some(false) => self.losses += 1,
+      None => self.draws += 1,
+    }
+  }
+}
+
+pub struct Book {
+  size: usize,
+  // Only the first this many plies of each game are recorded
+  max_plies: usize,
+  // Moves are stored as they'd be played in the canonical orientation
+  positions: HashMap<u64, Vec<(Move, Stats)>>,
+}
+
+impl Book {
+  pub fn new(size: usize, max_plies: usize) -> Self {
+    Book {
+      size,
+      max_plies,
+      positions: HashMap::new(),
+    }
+  }
+
+  pub fn size(&self) -> usize { self.size }
+
+  // Number of distinct positions, up to symmetry
+  pub fn len(&self) -> usize { self.positions.len() }
+
+  pub fn is_empty(&self) -> bool { self.positions.is_empty() }
+
+  // Record a game played from start. Returns false, without recording
+  // anything, if it's for another board size or has an illegal move.
+  pub fn add_game(&mut self, start: &Game, moves: &[Move], result: Winner) -> bool {
+    if start.size() != self.size {
+      return false;
+    }
+    let mut g = start.clone();
+    let mut seen = Vec::new();
+    for m in moves.iter().take(self.max_plies) {
+      if g.status().is_some() {
+        break;
+      }
+      if g.validate(m) != MoveValidity::Valid {
+        return false;
+      }
+      let (hash, canonical) = canonical_move(&g, *m);
+      let outcome = match result {
+        Winner::Draw => None,
+        Winner::Road(p) | Winner::Flat(p) | Winner::Other(p) => Some(p == g.cur_player()),
+      };
+      seen.push((hash, canonical, outcome));
+      g.execute(m);
+    }
+    for (hash, m, outcome) in seen {
+      let moves = self.positions.entry(hash).or_insert_with(Vec::new);
+      match moves.iter().position(|&(n, _)| n == m) {
+        Some(i) => moves[i].1.add(outcome),
+        None => {
+          let mut stats = Stats::default();
+          stats.add(outcome);
+          moves.push((m, stats));
+        },
+      }
+    }
+    true
+  }
+
+  // Record a game from a PTN file. Games without a result are skipped.
+  pub fn add_ptn(&mut self, ptn: &Ptn) -> bool {
+    let result = match ptn.result {
+      Some(result) => result,
+      None => return false,
+    };
+    let moves : Vec<Move> = ptn.moves.iter().map(|m| m.m).collect();
+    match ptn.start() {
+      Some(start) => self.add_game(&start, &moves, result),
+      None => false,
+    }
+  }
+
+  // Record a game from the playtak archive. Games the archive flagged as
+  // having a problem are skipped.
+  #[cfg(feature = "sqlite")]
+  pub fn add_record(&mut self, record: &Record) -> bool {
+    match (record.result, record.rules().and_then(Game::with_rules)) {
+      (Some(result), Some(start)) if record.is_clean() => self.add_game(&start, &record.moves, result),
+      _ => false,
+    }
+  }
+
+  // Book moves for a position, most played first
+  pub fn moves(&self, game: &Game) -> Vec<(Move, Stats)> {
+    if game.size() != self.size {
+      return Vec::new();
+    }
+    let (hash, sym) = game.canonical_hash();
+    let mut moves : Vec<(Move, Stats)> = match self.positions.get(&hash) {
+      Some(moves) => moves.iter().map(|&(m, stats)| (m.transform(sym.inverse(), self.size), stats)).collect(),
+      None => Vec::new(),
+    };
+    moves.sort_by(|a, b| b.1.count.cmp(&a.1.count));
+    moves
+  }
+
+  // Pick a book move with probability proportional to how often it was
+  // played, ignoring moves played fewer than min_count times. random can be
+  // any random number, i.e. from an rng or the clock.
+  pub fn choose(&self, game: &Game, min_count: u32, random: u64) -> Option<Move> {
+    let moves : Vec<(Move, Stats)> = self.moves(game).into_iter()
+      .filter(|&(_, stats)| stats.count >= min_count.max(1))
+      .collect();
+    let total : u64 = moves.iter().map(|&(_, stats)| u64::from(stats.count)).sum();
+    if total == 0 {
+      return None;
+    }
+    let mut pick = random % total;
+    for (m, stats) in moves {
+      if pick < u64::from(stats.count) {
+        return Some(m);
+      }
+      pick -= u64::from(stats.count);
+    }
+    None
+  }
+
+  // Binary format: "TAKBOOK1", the board size and ply limit, then each
+  // position's hash and moves. All numbers are little endian.
+  pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&[self.size as u8])?;
+    write_u32(w, self.max_plies as u32)?;
+    write_u32(w, self.positions.len() as u32)?;
+    // Sorted so the same book is always written the same way
+    let mut hashes : Vec<&u64> = self.positions.keys().collect();
+    hashes.sort();
+    for hash in hashes {
+      let moves = &self.positions[hash];
+      write_u64(w, *hash)?;
+      write_u32(w, moves.len() as u32)?;
+      for &(m, stats) in moves {
+        w.write_all(&encode_move(m))?;
+        for &n in [stats.count, stats.wins, stats.draws, stats.losses].iter() {
+          write_u32(w, n)?;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  pub fn read<R: Read>(r: &mut R) -> io::Result<Book> {
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+      return Err(invalid("not an opening book"));
+    }
+    let mut size = [0];
+    r.read_exact(&mut size)?;
+    let mut book = Book::new(size[0] as usize, read_u32(r)? as usize);
+    if ::game::new(book.size).is_none() {
+      return Err(invalid("invalid board size"));
+    }
+    for _ in 0 .. read_u32(r)? {
+      let hash = read_u64(r)?;
+      let count = read_u32(r)?;
+      let mut moves = Vec::new();
+      for _ in 0 .. count {
+        let mut bytes = [0; 7];
+        r.read_exact(&mut bytes)?;
+        let m = decode_move(bytes, book.size).ok_or_else(|| invalid("invalid move"))?;
+        let stats = Stats {
+          count: read_u32(r)?,
+          wins: read_u32(r)?,
+          draws: read_u32(r)?,
+          losses: read_u32(r)?,
+        };
+        moves.push((m, stats));
+      }
+      book.positions.insert(hash, moves);
+    }
+    Ok(book)
+  }
+}
+
+// Canonical hash of the position, along with m as it'd be played in the
+// canonical orientation. When the position is symmetric, so that several
+// transforms lead to the canonical one, the moves they give are all
+// equivalent and the smallest is used.
+fn canonical_move(game: &Game, m: Move) -> (u64, Move) {
+  let hashes : Vec<(u64, Symmetry)> = SYMMETRIES.iter().map(|&sym| (game.transform(sym).hash(), sym)).collect();
+  let hash = hashes.iter().map(|&(hash, _)| hash).min().unwrap();
+  let canonical = hashes.iter()
+    .filter(|&&(h, _)| h == hash)
+    .map(|&(_, sym)| m.transform(sym, game.size()))
+    .min_by_key(|&m| encode_move(m))
+    .unwrap();
+  (hash, canonical)
+}
+
+const MAGIC : &[u8; 8] = b"TAKBOOK1";
+
+// Kind (placement by piece, or movement by direction), square, range, and the
+// drop counts packed two to a byte
+fn encode_move(m: Move) -> [u8; 7] {
+  let mut bytes = [0; 7];
+  let loc = match m {
+    Move::Place(loc, piece) => {
+      bytes[0] = match piece { Piece::Flat => 0, Piece::Wall => 1, Piece::Cap => 2 };
+      loc
+    },
+    Move::Move(loc, dir, range, drops) => {
+      bytes[0] = match dir { Dir::Up => 3, Dir::Down => 4, Dir::Left => 5, Dir::Right => 6 };
+      bytes[2] = range;
+      for (i, &d) in drops.iter().enumerate() {
+        bytes[3 + i / 2] |= d << (4 * (i % 2));
+      }
+      loc
+    },
+  };
+  bytes[1] = loc.x << 4 | loc.y;
+  bytes
+}
+
+// Decodes a move for a board of the given size, or None if it doesn't fit on
+// the board
+fn decode_move(bytes: [u8; 7], size: usize) -> Option<Move> {
+  let loc = Loc { x: bytes[1] >> 4, y: bytes[1] & 0xf };
+  if loc.x as usize >= size || loc.y as usize >= size {
+    return None;
+  }
+  let dir = match bytes[0] {
+    0 => return Some(Move::Place(loc, Piece::Flat)),
+    1 => return Some(Move::Place(loc, Piece::Wall)),
+    2 => return Some(Move::Place(loc, Piece::Cap)),
+    3 => Dir::Up,
+    4 => Dir::Down,
+    5 => Dir::Left,
+    6 => Dir::Right,
+    _ => return None,
+  };
+  let mut drops = [0; 7];
+  for (i, d) in drops.iter_mut().enumerate() {
+    *d = bytes[3 + i / 2] >> (4 * (i % 2)) & 0xf;
+  }
+
+  // Each square passed over gets at least one piece, and no more can be
+  // carried than the board is wide
+  let range = bytes[2] as usize;
+  let (dx, dy) = match dir { Dir::Up => (0, 1), Dir::Down => (0, -1), Dir::Left => (-1, 0), Dir::Right => (1, 0) };
+  let end = (loc.x as isize + dx * range as isize, loc.y as isize + dy * range as isize);
+  if range == 0 || range > drops.len() || end.0 < 0 || end.1 < 0 || end.0 >= size as isize || end.1 >= size as isize {
+    return None;
+  }
+  if drops[.. range].contains(&0) || drops[range ..].iter().any(|&d| d != 0) {
+    return None;
+  }
+  if drops.iter().map(|&d| d as usize).sum::<usize>() > size {
+    return None;
+  }
+  Some(Move::Move(loc, dir, bytes[2], drops))
+}
+
+fn invalid(msg: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn write_u32<W: Write>(w: &mut W, n: u32) -> io::Result<()> {
+  w.write_all(&[n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8])
+}
+
+fn write_u64<W: Write>(w: &mut W, n: u64) -> io::Result<()> {
+  write_u32(w, n as u32)?;
+  write_u32(w, (n >> 32) as u32)
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+  let mut b = [0; 4];
+  r.read_exact(&mut b)?;
+  Ok(u32::from(b[0]) | u32::from(b[1]) << 8 | u32::from(b[2]) << 16 | u32::from(b[3]) << 24)
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+  let low = read_u32(r)?;
+  Ok(u64::from(low) | u64::from(read_u32(r)?) << 32)
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Book, Stats};
+  use game::{self, Player, Winner};
+  use ptn;
+
+  fn moves(ptn: &str) -> Vec<game::Move> {
+    ptn.split_whitespace().map(|m| ptn::parse_move(m).unwrap()).collect()
+  }
+
+  #[test]
+  fn symmetry() {
+    let start = game::new(5).unwrap();
+    let mut book = Book::new(5, 4);
+    assert!(book.add_game(&start, &moves("a1 e5 c3 b3"), Winner::Road(Player::White)));
+    assert!(book.add_game(&start, &moves("e5 a1 c3 c4"), Winner::Flat(Player::Black)));
+    assert!(book.add_game(&start, &moves("a5 e1 c2"), Winner::Draw));
+    assert!(!book.add_game(&start, &moves("a1 a1"), Winner::Draw));
+    assert!(!book.add_game(&game::new(6).unwrap(), &moves("a1"), Winner::Draw));
+
+    // All three openings are corners, and the replies are the opposite corners
+    let first = book.moves(&start);
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0].1, Stats { count: 3, wins: 1, draws: 1, losses: 1 });
+
+    // Moves come back in the orientation of the position asked about
+    let mut g = start.clone();
+    g.execute(&moves("e1")[0]);
+    let replies = book.moves(&g);
+    assert_eq!(replies.len(), 1);
+    assert_eq!(ptn::to_string(&replies[0].0), "a5");
+    g.execute(&replies[0].0);
+    let third = book.moves(&g);
+    assert_eq!(third.len(), 2);
+    assert_eq!(third[0].1.count, 2);
+    assert_eq!(ptn::to_string(&third[0].0), "c3");
+    assert_eq!(third[1].1.score(), 0.5);
+
+    assert_eq!(book.choose(&g, 1, 0), Some(third[0].0));
+    assert_eq!(book.choose(&g, 1, 2), Some(third[1].0));
+    assert_eq!(book.choose(&g, 2, 2), Some(third[0].0));
+    assert_eq!(book.choose(&g, 3, 2), None);
+  }
+
+  #[test]
+  fn sources() {
+    let mut book = Book::new(5, 10);
+    let game = ptn::parse("[Size \"5\"]\n\n1. a1 e5 2. Cc3 c4 3. c3+ 1-0\n").unwrap();
+    assert!(book.add_ptn(&game));
+    let unfinished = ptn::parse("[Size \"5\"]\n\n1. a1 e5\n").unwrap();
+    assert!(!book.add_ptn(&unfinished));
+    let midgame = ptn::parse("[Size \"5\"]\n[TPS \"x5/x5/x5/x5/2,x3,1 1 2\"]\n\n2. c3 0-1\n").unwrap();
+    assert!(book.add_ptn(&midgame));
+    assert_eq!(book.len(), 6);
+  }
+
+  // Archive games are replayed with the piece counts they were played with
+  #[cfg(feature = "sqlite")]
+  #[test]
+  fn records() {
+    use std::time::UNIX_EPOCH;
+    use archive::Record;
+    let mut record = Record {
+      id: 1,
+      date: UNIX_EPOCH,
+      white: "alice".to_string(),
+      black: "bob".to_string(),
+      size: 4,
+      komi: game::Komi::default(),
+      flats: 15,
+      caps: 1,
+      moves: moves("a1 d4 Cb2"),
+      result: Some(Winner::Other(Player::White)),
+      problem: None,
+    };
+    let mut book = Book::new(4, 10);
+    assert!(book.add_record(&record));
+    assert_eq!(book.len(), 3);
+    record.caps = 0;
+    assert!(!Book::new(4, 10).add_record(&record));
+  }
+
+  #[test]
+  fn read_write() {
+    let mut book = Book::new(4, 10);
+    let start = game::new(4).unwrap();
+    assert!(book.add_game(&start, &moves("a1 d4 b2 c3 b2> b2 Sb3"), Winner::Other(Player::Black)));
+    assert!(book.add_game(&start, &moves("a1 d4 b2 c3 a2 c2 a2- b1 2a1>11"), Winner::Road(Player::Black)));
+    let mut bytes = Vec::new();
+    book.write(&mut bytes).unwrap();
+
+    let read = Book::read(&mut &bytes[..]).unwrap();
+    assert_eq!((read.size, read.max_plies), (4, 10));
+    assert_eq!(read.positions, book.positions);
+    let mut again = Vec::new();
+    read.write(&mut again).unwrap();
+    assert_eq!(again, bytes);
+    assert!(Book::read(&mut &bytes[1 ..]).is_err());
+    assert!(Book::read(&mut &bytes[.. bytes.len() - 1]).is_err());
+
+    // Moves that don't fit on the board. The first move follows the header,
+    // a hash and a move count.
+    let first = 8 + 1 + 4 + 4 + 8 + 4;
+    let corrupt = |i: usize, b: u8| {
+      let mut bytes = bytes.clone();
+      bytes[first + i] = b;
+      Book::read(&mut &bytes[..]).err().unwrap().to_string()
+    };
+    assert_eq!(corrupt(1, 0x04), "invalid move");
+    assert_eq!(corrupt(1, 0x40), "invalid move");
+    assert_eq!(corrupt(0, 7), "invalid move");
+    let mut bytes = bytes.clone();
+    bytes[first .. first + 7].copy_from_slice(&[3, 0x02, 2, 0x11, 0, 0, 0]);
+    assert!(Book::read(&mut &bytes[..]).is_err());
+    bytes[first .. first + 7].copy_from_slice(&[3, 0x01, 2, 0x01, 0, 0, 0]);
+    assert!(Book::read(&mut &bytes[..]).is_err());
+    bytes[first .. first + 7].copy_from_slice(&[3, 0x01, 2, 0x32, 0, 0, 0]);
+    assert!(Book::read(&mut &bytes[..]).is_err());
+    bytes[first .. first + 7].copy_from_slice(&[3, 0x01, 2, 0x11, 0, 0, 0]);
+    assert!(Book::read(&mut &bytes[..]).is_ok());
+  }
+}
@@ -0,0 +1,413 @@
+use game::{Game, Move, MoveValidity, Player, Rules, Undo, Winner};
+use playtak::{self, ClientMessage, Seek, ServerMessage};
+
+// Client side of the playtak protocol for bots. It doesn't do any IO: lines
+// from the server go into Client::handle, which returns the lines to send back.
+
+// Time left on each player's clock, in seconds
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Clock {
+  pub white: u32,
+  pub black: u32,
+}
+
+impl Clock {
+  pub fn get(&self, player: Player) -> u32 {
+    match player {
+      Player::White => self.white,
+      Player::Black => self.black,
+    }
+  }
+}
+
+// A game the client is playing
+#[derive(Debug,Clone)]
+pub struct OnlineGame {
+  pub id: u32,
+  pub white: String,
+  pub black: String,
+  // Color the bot is playing
+  pub color: Player,
+  pub game: Game,
+  pub clock: Clock,
+  undos: Vec<Undo>,
+}
+
+pub trait Bot {
+  // Move for the bot to play. Only called when it's the bot's turn.
+  fn choose_move(&mut self, game: &OnlineGame) -> Move;
+
+  fn game_started(&mut self, _game: &OnlineGame) {}
+
+  fn game_over(&mut self, _game: &OnlineGame, _result: Winner) {}
+
+  // Whether to accept a draw offered by the opponent
+  fn accept_draw(&mut self, _game: &OnlineGame) -> bool { false }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Mode {
+  // Post this seek whenever the client isn't playing
+  Seek(Seek),
+  // Accept seeks, only from the named player if there is one
+  Accept(Option<String>),
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Config {
+  // Sent to the server to identify the client software
+  pub client_name: String,
+  // Name and password, or None to log in as a guest
+  pub login: Option<(String, String)>,
+  pub mode: Mode,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum ClientError {
+  LoginFailed,
+  // The server sent a move that isn't legal in the client's copy of the game
+  IllegalMove(Move, MoveValidity),
+  // The bot chose a move that isn't legal
+  IllegalBotMove(Move, MoveValidity),
+  // The server started the game with this id with rules the client can't play
+  UnsupportedRules(u32),
+}
+
+pub struct Client<B> {
+  config: Config,
+  bot: B,
+  // Name the server logged us in as, i.e. Guest123
+  name: Option<String>,
+  game: Option<OnlineGame>,
+  // Seek we've tried to accept, if we're waiting to hear back
+  accepting: Option<u32>,
+  games_played: u32,
+}
+
+impl<B: Bot> Client<B> {
+  pub fn new(config: Config, bot: B) -> Self {
+    Client { config, bot, name: None, game: None, accepting: None, games_played: 0 }
+  }
+
+  #[inline]
+  pub fn name(&self) -> Option<&str> { self.name.as_ref().map(|s| s.as_str()) }
+
+  // Game in progress, if any
+  #[inline]
+  pub fn game(&self) -> Option<&OnlineGame> { self.game.as_ref() }
+
+  #[inline]
+  pub fn games_played(&self) -> u32 { self.games_played }
+
+  #[inline]
+  pub fn bot(&mut self) -> &mut B { &mut self.bot }
+
+  // Handle a line from the server, returning the lines to send in response.
+  // Lines the client doesn't understand or care about are ignored.
+  pub fn handle(&mut self, line: &str) -> Result<Vec<ClientMessage>, ClientError> {
+    let msg = match playtak::parse_server_message(line) {
+      Ok(msg) => msg,
+      Err(_) => return Ok(Vec::new()),
+    };
+    let mut out = Vec::new();
+    match msg {
+      ServerMessage::LoginOrRegister => {
+        out.push(ClientMessage::Client(self.config.client_name.clone()));
+        out.push(match self.config.login {
+          Some((ref name, ref password)) => ClientMessage::Login(name.clone(), password.clone()),
+          None => ClientMessage::LoginGuest,
+        });
+      },
+      ServerMessage::LoggedIn(name) => {
+        self.name = Some(name);
+        self.look_for_game(&mut out);
+      },
+      ServerMessage::Nok if self.name.is_none() => return Err(ClientError::LoginFailed),
+      ServerMessage::SeekNew(id, name, _) => {
+        let wanted = match self.config.mode {
+          Mode::Accept(ref from) => from.as_ref().map_or(true, |from| *from == name),
+          Mode::Seek(_) => false,
+        };
+        if wanted && self.game.is_none() && self.accepting.is_none() && Some(&name) != self.name.as_ref() {
+          self.accepting = Some(id);
+          out.push(ClientMessage::Accept(id));
+        }
+      },
+      ServerMessage::SeekRemove(id, _, _) => {
+        // Someone else got there first
+        if self.accepting == Some(id) && self.game.is_none() {
+          self.accepting = None;
+        }
+      },
+      ServerMessage::GameStart { game, size, white, black, color, time, komi, flats, caps } => {
+        self.accepting = None;
+        let rules = Rules::new(size).map(|r| Rules { komi, flats, caps, ..r });
+        let g = match rules.and_then(Game::with_rules) {
+          Some(g) => g,
+          None => return Err(ClientError::UnsupportedRules(game)),
+        };
+        self.game = Some(OnlineGame {
+          id: game,
+          white,
+          black,
+          color,
+          game: g,
+          clock: Clock { white: time, black: time },
+          undos: Vec::new(),
+        });
+        self.bot.game_started(self.game.as_ref().unwrap());
+        self.play(&mut out)?;
+      },
+      ServerMessage::Move(id, m) => {
+        if let Some(ref mut online) = self.game {
+          if online.id == id {
+            match online.game.validate(&m) {
+              MoveValidity::Valid => online.undos.push(online.game.execute(&m)),
+              v => return Err(ClientError::IllegalMove(m, v)),
+            }
+          }
+        }
+        self.play(&mut out)?;
+      },
+      ServerMessage::Time(id, white, black) => {
+        if let Some(ref mut online) = self.game {
+          if online.id == id { online.clock = Clock { white, black }; }
+        }
+      },
+      ServerMessage::Undo(id) => {
+        if let Some(ref mut online) = self.game {
+          if online.id == id {
+            if let Some(undo) = online.undos.pop() { online.game.undo(&undo); }
+          }
+        }
+        self.play(&mut out)?;
+      },
+      ServerMessage::OfferDraw(id) => {
+        if let Some(ref online) = self.game {
+          if online.id == id && self.bot.accept_draw(online) {
+            out.push(ClientMessage::OfferDraw(id));
+          }
+        }
+      },
+      ServerMessage::Over(id, result) => self.finish(id, result, &mut out),
+      ServerMessage::Abandoned(id) => {
+        // The opponent left, so the game goes to us
+        let color = self.game.as_ref().map(|online| online.color);
+        if let Some(color) = color {
+          self.finish(id, Winner::Other(color), &mut out);
+        }
+      },
+      _ => {},
+    }
+    Ok(out)
+  }
+
+  fn look_for_game(&mut self, out: &mut Vec<ClientMessage>) {
+    if let Mode::Seek(seek) = self.config.mode {
+      out.push(ClientMessage::Seek(seek));
+    }
+  }
+
+  // Ask the bot for a move if it's its turn
+  fn play(&mut self, out: &mut Vec<ClientMessage>) -> Result<(), ClientError> {
+    let online = match self.game {
+      Some(ref mut online) => online,
+      None => return Ok(()),
+    };
+    if online.game.cur_player() != online.color || online.game.status().is_some() {
+      return Ok(());
+    }
+    let m = self.bot.choose_move(online);
+    match online.game.validate(&m) {
+      MoveValidity::Valid => online.undos.push(online.game.execute(&m)),
+      v => return Err(ClientError::IllegalBotMove(m, v)),
+    }
+    out.push(ClientMessage::Move(online.id, m));
+    Ok(())
+  }
+
+  fn finish(&mut self, id: u32, result: Winner, out: &mut Vec<ClientMessage>) {
+    if self.game.as_ref().map_or(true, |online| online.id != id) {
+      return;
+    }
+    let online = self.game.take().unwrap();
+    self.bot.game_over(&online, result);
+    self.games_played += 1;
+    self.look_for_game(out);
+  }
+}
+
+// Plays a scripted session against a client, for testing bots without a
+// server. Lines starting with "> " are sent to the client, and lines starting
+// with "< " are what the client is expected to send back, in order.
+pub struct MockServer {
+  script: Vec<(bool, String)>,
+}
+
+impl MockServer {
+  pub fn new(script: &str) -> Self {
+    let script = script.lines()
+      .map(|line| line.trim())
+      .filter(|line| !line.is_empty())
+      .map(|line| {
+        if line.starts_with("> ") { (true, line[2 ..].to_string()) }
+        else if line.starts_with("< ") { (false, line[2 ..].to_string()) }
+        else { panic!("script lines must start with > or <: {}", line) }
+      })
+      .collect();
+    MockServer { script }
+  }
+
+  // Run the script, returning a description of the first difference
+  pub fn run<B: Bot>(&self, client: &mut Client<B>) -> Result<(), String> {
+    let mut sent : Vec<String> = Vec::new();
+    for (n, &(to_client, ref line)) in self.script.iter().enumerate() {
+      if to_client {
+        if let Some(extra) = sent.first() {
+          return Err(format!("line {}: client sent {:?} before {:?}", n + 1, extra, line));
+        }
+        let out = client.handle(line).map_err(|e| format!("line {}: {:?}", n + 1, e))?;
+        sent.extend(out.iter().map(|msg| msg.to_string()));
+      } else {
+        if sent.is_empty() {
+          return Err(format!("line {}: expected {:?} but the client sent nothing", n + 1, line));
+        }
+        let msg = sent.remove(0);
+        if msg != *line {
+          return Err(format!("line {}: expected {:?} but the client sent {:?}", n + 1, line, msg));
+        }
+      }
+    }
+    match sent.first() {
+      Some(extra) => Err(format!("client sent {:?} after the end of the script", extra)),
+      None => Ok(()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Bot, Client, ClientError, Config, MockServer, Mode, OnlineGame};
+  use game::{Move, Winner};
+  use playtak::Seek;
+
+  // Plays the first legal move and takes any draw
+  #[derive(Default)]
+  struct FirstMove {
+    results: Vec<Winner>,
+    seen_clock: Vec<u32>,
+  }
+
+  impl Bot for FirstMove {
+    fn choose_move(&mut self, online: &OnlineGame) -> Move {
+      self.seen_clock.push(online.clock.get(online.color));
+      online.game.moves().next().unwrap()
+    }
+
+    fn game_over(&mut self, _: &OnlineGame, result: Winner) {
+      self.results.push(result);
+    }
+
+    fn accept_draw(&mut self, _: &OnlineGame) -> bool { true }
+  }
+
+  fn config(mode: Mode) -> Config {
+    Config { client_name: "rustak-test".to_string(), login: None, mode }
+  }
+
+  #[test]
+  fn seek_and_play() {
+    let script = "
+      > Welcome!
+      > Login or Register
+      < Client rustak-test
+      < Login Guest
+      > Welcome Guest7!
+      < Seek 4 600 10 A 0 15 0 0 0
+      > Seek new 12 Guest7 4 600 10 A 0 15 0 0 0
+      > Seek remove 12 Guest7 4 600 10 A 0 15 0 0 0
+      > Game Start 200 4 Guest7 vs alice white 600 0 15 0
+      < Game#200 P A1
+      > Game#200 Time 598 600
+      > Game#200 P D4
+      < Game#200 P B1
+      > Game#201 P C1
+      > Game#200 Time 590 597
+      > Game#200 P D3
+      < Game#200 M B1 B2 1
+      > Game#200 RequestUndo
+      > Game#200 Undo
+      < Game#200 M B1 B2 1
+      > Game#200 OfferDraw
+      < Game#200 OfferDraw
+      > Game#200 Over 1/2-1/2
+      < Seek 4 600 10 A 0 15 0 0 0
+      > Game Start 201 4 bob vs Guest7 black 300 0 15 0
+      > Game#201 P A1
+      < Game#201 P B1
+      > Game#201 Abandoned. bob quit
+      < Seek 4 600 10 A 0 15 0 0 0
+    ";
+    let seek = Seek::new(4, 600, 10).unwrap();
+    let mut client = Client::new(config(Mode::Seek(seek)), FirstMove::default());
+    MockServer::new(script).run(&mut client).unwrap();
+    assert_eq!(client.name(), Some("Guest7"));
+    assert_eq!(client.games_played(), 2);
+    assert!(client.game().is_none());
+    assert_eq!(client.bot().results, vec![Winner::Draw, Winner::Other(::game::Player::Black)]);
+    assert_eq!(client.bot().seen_clock, vec![600, 598, 590, 590, 300]);
+  }
+
+  #[test]
+  fn accept() {
+    let script = "
+      > Login or Register
+      < Client rustak-test
+      < Login rustak secret
+      > Welcome rustak!
+      > Seek new 3 carol 5 600 10 A 0 21 1 0 0
+      > Seek new 4 alice 5 600 10 A 0 21 1 0 0
+      < Accept 4
+      > Seek new 5 alice 5 600 10 A 0 21 1 0 0
+      > Seek remove 4 alice 5 600 10 A 0 21 1 0 0
+      > Seek new 6 alice 5 600 10 A 0 21 1 0 0
+      < Accept 6
+      > Game Start 7 5 alice vs rustak black 600 0 21 1
+      > Game#7 P E5
+      < Game#7 P A1
+      > Game#7 Over R-0
+    ";
+    let mut cfg = config(Mode::Accept(Some("alice".to_string())));
+    cfg.login = Some(("rustak".to_string(), "secret".to_string()));
+    let mut client = Client::new(cfg, FirstMove::default());
+    MockServer::new(script).run(&mut client).unwrap();
+    assert_eq!(client.bot().results, vec![Winner::Road(::game::Player::White)]);
+  }
+
+  #[test]
+  fn errors() {
+    let mut client = Client::new(config(Mode::Accept(None)), FirstMove::default());
+    assert_eq!(client.handle("Login or Register").unwrap().len(), 2);
+    assert_eq!(client.handle("NOK"), Err(ClientError::LoginFailed));
+    assert_eq!(client.handle("GameList Add Game#1 a vs b, 5, 600, 10, 0, 0, 21, 1"), Ok(vec![]));
+
+    // The script catches clients that say the wrong thing
+    let script = "
+      > Login or Register
+      < Client rustak-test
+      < Login Guest
+      < Seek 5 600 10 A 0 21 1 0 0
+    ";
+    let mut client = Client::new(config(Mode::Accept(None)), FirstMove::default());
+    assert!(MockServer::new(script).run(&mut client).is_err());
+
+    // A move the client's game doesn't allow means it's out of sync
+    let mut client = Client::new(config(Mode::Accept(None)), FirstMove::default());
+    client.handle("Game Start 1 5 alice vs bob black 600").unwrap();
+    assert!(client.handle("Game#1 P A1 C").is_err());
+
+    let mut client = Client::new(config(Mode::Accept(None)), FirstMove::default());
+    assert_eq!(client.handle("Game Start 2 5 alice vs bob black 600 0 0 1"), Err(ClientError::UnsupportedRules(2)));
+    assert!(client.game().is_none());
+  }
+}
@@ -0,0 +1,397 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use eval::Evaluator;
+use game::{Game, Move, Winner};
+
+// Score for a win on the spot. Wins further away score a little less, so the
+// engine goes for the quickest win and the slowest loss.
+pub const WIN : i32 = 1_000_000;
+pub const MAX_PLY : usize = 128;
+
+// Whether a score is a forced win or loss
+pub fn is_win_score(score: i32) -> bool {
+  score.abs() > WIN - MAX_PLY as i32
+}
+
+#[derive(Debug,Clone,Copy,Default)]
+pub struct Limits {
+  pub depth: Option<u32>,
+  pub nodes: Option<u64>,
+  pub time: Option<Duration>,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct SearchResult {
+  // None only if there are no legal moves
+  pub best: Option<Move>,
+  // From the point of view of the player to move
+  pub score: i32,
+  // Depth of the last completed iteration
+  pub depth: u32,
+  pub pv: Vec<Move>,
+  pub nodes: u64,
+  pub time: Duration,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum Bound {
+  Exact,
+  Lower,
+  Upper,
+}
+
+#[derive(Debug,Clone,Copy)]
+struct TtEntry {
+  hash: u64,
+  m: Option<Move>,
+  score: i32,
+  depth: u32,
+  bound: Bound,
+}
+
+// Negamax alpha-beta search with iterative deepening and a transposition table.
+// The evaluation scores a position for the player to move, and can be any
+// Evaluator, including a closure.
+pub struct Engine<E> {
+  eval: E,
+  tt: Vec<Option<TtEntry>>,
+  killers: Vec<[Option<Move>; 2]>,
+  history: HashMap<Move, u32>,
+  stop: Arc<AtomicBool>,
+
+  // State of the current search
+  limits: Limits,
+  start: Instant,
+  nodes: u64,
+  aborted: bool,
+}
+
+impl<E: Evaluator> Engine<E> {
+  pub fn new(eval: E) -> Self {
+    Engine::with_tt_size(eval, 1 << 20)
+  }
+
+  // tt_size is the number of transposition table entries, rounded up to a power of two
+  pub fn with_tt_size(eval: E, tt_size: usize) -> Self {
+    Engine {
+      eval,
+      tt: vec![None; tt_size.next_power_of_two()],
+      killers: vec![[None; 2]; MAX_PLY],
+      history: HashMap::new(),
+      stop: Arc::new(AtomicBool::new(false)),
+      limits: Limits::default(),
+      start: Instant::now(),
+      nodes: 0,
+      aborted: false,
+    }
+  }
+
+  // The evaluation, i.e. to change its weights between searches
+  #[inline]
+  pub fn evaluator(&mut self) -> &mut E { &mut self.eval }
+
+  // Flag that stops a running search when set, i.e. from another thread. The
+  // search clears it when it returns, so setting it just before a search starts
+  // still stops that search.
+  pub fn stop_handle(&self) -> Arc<AtomicBool> {
+    self.stop.clone()
+  }
+
+  // Forget everything learned from earlier searches, i.e. for a new game
+  pub fn clear(&mut self) {
+    for entry in self.tt.iter_mut() { *entry = None; }
+    self.history.clear();
+  }
+
+  pub fn search(&mut self, game: &Game, limits: Limits) -> SearchResult {
+    self.search_with_info(game, limits, |_| {})
+  }
+
+  // Search, calling info with the result of each completed iteration
+  pub fn search_with_info<F: FnMut(&SearchResult)>(&mut self, game: &Game, limits: Limits, mut info: F) -> SearchResult {
+    self.limits = limits;
+    self.start = Instant::now();
+    self.nodes = 0;
+    self.aborted = false;
+    for k in self.killers.iter_mut() { *k = [None; 2]; }
+
+    let mut g = game.clone();
+    let mut result = SearchResult {
+      best: g.moves().next(),
+      score: 0,
+      depth: 0,
+      pv: Vec::new(),
+      nodes: 0,
+      time: Duration::from_secs(0),
+    };
+    if result.best.is_none() || g.status().is_some() {
+      self.stop.store(false, Ordering::Relaxed);
+      return result;
+    }
+
+    let max_depth = limits.depth.unwrap_or(MAX_PLY as u32 - 1).min(MAX_PLY as u32 - 1);
+    for depth in 1 ..= max_depth {
+      let mut pv = Vec::new();
+      let score = self.negamax(&mut g, depth, 0, -WIN, WIN, &mut pv);
+      if self.aborted {
+        break;
+      }
+      result = SearchResult {
+        best: pv.first().cloned(),
+        score,
+        depth,
+        pv,
+        nodes: self.nodes,
+        time: self.start.elapsed(),
+      };
+      info(&result);
+      if is_win_score(score) {
+        break;
+      }
+    }
+    self.stop.store(false, Ordering::Relaxed);
+    result.nodes = self.nodes;
+    result.time = self.start.elapsed();
+    result
+  }
+
+  fn check_limits(&mut self) {
+    if self.limits.nodes.map_or(false, |n| self.nodes >= n)
+    || self.limits.time.map_or(false, |t| self.start.elapsed() >= t) {
+      self.aborted = true;
+    }
+  }
+
+  fn negamax(&mut self, g: &mut Game, depth: u32, ply: usize, mut alpha: i32, beta: i32, pv: &mut Vec<Move>) -> i32 {
+    pv.clear();
+    self.nodes += 1;
+    if self.nodes % 1024 == 0 {
+      self.check_limits();
+    }
+    if self.aborted || self.stop.load(Ordering::Relaxed) {
+      self.aborted = true;
+      return 0;
+    }
+
+    if let Some(w) = g.status() {
+      let player = g.cur_player();
+      return match w {
+        Winner::Road(p) | Winner::Flat(p) | Winner::Other(p) => {
+          if p == player { WIN - ply as i32 } else { ply as i32 - WIN }
+        },
+        Winner::Draw => 0,
+      };
+    }
+    if depth == 0 || ply >= MAX_PLY - 1 {
+      return self.eval.evaluate(g);
+    }
+
+    let hash = g.hash();
+    let slot = (hash as usize) & (self.tt.len() - 1);
+    let mut tt_move = None;
+    if let Some(entry) = self.tt[slot] {
+      if entry.hash == hash {
+        tt_move = entry.m;
+        if entry.depth >= depth && ply > 0 {
+          let score = from_tt(entry.score, ply);
+          let cutoff = match entry.bound {
+            Bound::Exact => true,
+            Bound::Lower => score >= beta,
+            Bound::Upper => score <= alpha,
+          };
+          if cutoff {
+            if let Some(m) = entry.m { pv.push(m); }
+            return score;
+          }
+        }
+      }
+    }
+
+    let mut moves : Vec<Move> = g.moves().collect();
+    {
+      let killers = self.killers[ply];
+      let history = &self.history;
+      moves.sort_by_key(|m| Reverse(
+        if Some(*m) == tt_move { u32::max_value() }
+        else if Some(*m) == killers[0] { u32::max_value() - 1 }
+        else if Some(*m) == killers[1] { u32::max_value() - 2 }
+        else { history.get(m).cloned().unwrap_or(0) }
+      ));
+    }
+
+    let alpha_orig = alpha;
+    let mut best_score = -WIN;
+    let mut best_move = None;
+    let mut child_pv = Vec::new();
+    for m in moves {
+      let undo = g.execute(&m);
+      let score = -self.negamax(g, depth - 1, ply + 1, -beta, -alpha, &mut child_pv);
+      g.undo(&undo);
+      if self.aborted {
+        return 0;
+      }
+
+      if score > best_score {
+        best_score = score;
+        best_move = Some(m);
+      }
+      if score > alpha {
+        alpha = score;
+        pv.clear();
+        pv.push(m);
+        pv.extend_from_slice(&child_pv);
+      }
+      if alpha >= beta {
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(m) {
+          killers[1] = killers[0];
+          killers[0] = Some(m);
+        }
+        *self.history.entry(m).or_insert(0) += depth * depth;
+        break;
+      }
+    }
+
+    let bound = if best_score <= alpha_orig { Bound::Upper }
+      else if best_score >= beta { Bound::Lower }
+      else { Bound::Exact };
+    let replace = match self.tt[slot] {
+      Some(entry) => entry.hash != hash || entry.depth <= depth,
+      None => true,
+    };
+    if replace {
+      self.tt[slot] = Some(TtEntry { hash, m: best_move, score: to_tt(best_score, ply), depth, bound });
+    }
+    best_score
+  }
+}
+
+// Win scores are stored relative to the node rather than the root, so they
+// stay correct when the position comes up at a different ply
+fn to_tt(score: i32, ply: usize) -> i32 {
+  if !is_win_score(score) { score } else if score > 0 { score + ply as i32 } else { score - ply as i32 }
+}
+
+fn from_tt(score: i32, ply: usize) -> i32 {
+  if !is_win_score(score) { score } else if score > 0 { score - ply as i32 } else { score + ply as i32 }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Engine, Limits, WIN, is_win_score};
+  use eval::Handcrafted;
+  use std::sync::atomic::Ordering;
+  use std::time::Duration;
+  use game::{Game, Loc, Move, Piece};
+  use ptn;
+  use tps;
+
+  // Flats on top for the player to move minus the opponent's
+  fn flat_diff(g: &Game) -> i32 {
+    let mut score = 0;
+    for y in 0 .. g.size() as u8 {
+      for x in 0 .. g.size() as u8 {
+        let loc = Loc { x, y };
+        if g.top(loc) == Some(Piece::Flat) {
+          score += if g.stack(loc).get(0) == g.cur_player() { 100 } else { -100 };
+        }
+      }
+    }
+    score
+  }
+
+  #[test]
+  fn road_in_one() {
+    let g = tps::parse("x5/x5/2,2,x3/x5/1,1,1,1,x 1 5").unwrap();
+    let mut engine = Engine::new(flat_diff);
+    let res = engine.search(&g, Limits { depth: Some(3), ..Limits::default() });
+    assert_eq!(res.best, ptn::parse_move("e1"));
+    assert_eq!(res.score, WIN - 1);
+    assert_eq!(res.depth, 1);
+  }
+
+  #[test]
+  fn blocks_threat() {
+    // Black has to block c1, and at depth 2 can see why
+    let g = tps::parse("x5/x5/2,2,x3/x5/1,1,x,1,1 2 4").unwrap();
+    let mut engine = Engine::new(flat_diff);
+    let mut depths = Vec::new();
+    let res = engine.search_with_info(&g, Limits { depth: Some(3), ..Limits::default() }, |info| depths.push(info.depth));
+    assert_eq!(depths, vec![1, 2, 3]);
+    match res.best {
+      Some(Move::Place(loc, _)) => assert_eq!(loc, Loc { x: 2, y: 0 }),
+      m => panic!("{:?}", m),
+    }
+    assert_eq!(res.pv[0], res.best.unwrap());
+    assert!(!is_win_score(res.score));
+  }
+
+  #[test]
+  fn handcrafted() {
+    let g = tps::parse("x5/x5/2,2,x3/x5/1,1,x,1,1 2 4").unwrap();
+    let mut engine = Engine::new(Handcrafted::default());
+    let res = engine.search(&g, Limits { depth: Some(2), ..Limits::default() });
+    match res.best {
+      Some(Move::Place(loc, _)) => assert_eq!(loc, Loc { x: 2, y: 0 }),
+      m => panic!("{:?}", m),
+    }
+
+    // Weights can be changed between searches
+    let g = ::game::new(5).unwrap();
+    let limits = Limits { depth: Some(1), ..Limits::default() };
+    let before = engine.search(&g, limits).score;
+    engine.evaluator().tempo = 0;
+    engine.clear();
+    assert_eq!(engine.search(&g, limits).score, before + Handcrafted::default().tempo);
+  }
+
+  #[test]
+  fn pv_is_legal() {
+    let mut g = tps::parse("x,2,x2,1/x,2,1,x2/x,12C,1,x2/2,x,1C,x2/x,1,x3 2 7").unwrap();
+    let mut engine = Engine::new(flat_diff);
+    let res = engine.search(&g, Limits { depth: Some(4), ..Limits::default() });
+    assert!(res.nodes > 0);
+    assert_eq!(res.pv[0], res.best.unwrap());
+    for m in res.pv.iter() {
+      assert_eq!(g.validate(m), ::game::MoveValidity::Valid);
+      g.execute(m);
+    }
+
+    // A second search gets the same answer, with help from the transposition table
+    let g = tps::parse("x,2,x2,1/x,2,1,x2/x,12C,1,x2/2,x,1C,x2/x,1,x3 2 7").unwrap();
+    let again = engine.search(&g, Limits { depth: Some(4), ..Limits::default() });
+    assert_eq!((again.best, again.score), (res.best, res.score));
+    assert!(again.nodes < res.nodes);
+  }
+
+  #[test]
+  fn limits() {
+    let g = ::game::new(6).unwrap();
+    let mut engine = Engine::with_tt_size(flat_diff, 1024);
+    let res = engine.search(&g, Limits { nodes: Some(5000), ..Limits::default() });
+    assert!(res.best.is_some());
+    assert!(res.nodes < 6000);
+
+    let res = engine.search(&g, Limits { time: Some(Duration::from_millis(20)), ..Limits::default() });
+    assert!(res.best.is_some());
+    assert!(res.time < Duration::from_millis(500));
+
+    // Stopping from the info callback ends the search during the next iteration
+    engine.clear();
+    let stop = engine.stop_handle();
+    let res = engine.search_with_info(&g, Limits::default(), |_| stop.store(true, Ordering::Relaxed));
+    assert_eq!(res.depth, 1);
+    assert!(res.best.is_some());
+    assert!(!stop.load(Ordering::Relaxed));
+
+    // A stop that comes in before the search starts isn't lost
+    stop.store(true, Ordering::Relaxed);
+    let res = engine.search(&g, Limits::default());
+    assert_eq!(res.depth, 0);
+    assert!(res.best.is_some());
+    assert!(!stop.load(Ordering::Relaxed));
+  }
+}
@@ -0,0 +1,190 @@
+use game::{Game, Loc, Piece, Player};
+
+// Static evaluation of a position, from the point of view of the player to
+// move. Higher is better.
+pub trait Evaluator {
+  fn evaluate(&self, game: &Game) -> i32;
+}
+
+impl<F: Fn(&Game) -> i32> Evaluator for F {
+  #[inline]
+  fn evaluate(&self, game: &Game) -> i32 {
+    self(game)
+  }
+}
+
+// Weighted sum of simple positional features. All the weights are public, so
+// they can be tuned without rebuilding.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Handcrafted {
+  // Pieces on top of a stack, by type
+  pub flat: i32,
+  pub wall: i32,
+  pub cap: i32,
+  // Pieces still in reserve
+  pub reserve_flat: i32,
+  pub reserve_cap: i32,
+  // Pieces under the top of a stack, by whether they belong to the player
+  // controlling it
+  pub captive: i32,
+  pub support: i32,
+  // Per step towards the center, for each piece on top and extra for capstones
+  pub center: i32,
+  pub cap_center: i32,
+  // For each group of connected road pieces, times the square of the number of
+  // rows or columns it spans beyond the first
+  pub group: i32,
+  // Bonus for having the move
+  pub tempo: i32,
+}
+
+impl Default for Handcrafted {
+  fn default() -> Self {
+    Handcrafted {
+      flat: 100,
+      wall: 40,
+      cap: 80,
+      reserve_flat: 10,
+      reserve_cap: 40,
+      captive: -10,
+      support: 20,
+      center: 6,
+      cap_center: 10,
+      group: 12,
+      tempo: 30,
+    }
+  }
+}
+
+impl Handcrafted {
+  // Score of the position for player, not counting the tempo bonus
+  fn score(&self, game: &Game, player: Player) -> i32 {
+    let size = game.size();
+    let mut score = 0;
+
+    let reserves = game.reserves(player);
+    score += self.reserve_flat * i32::from(reserves.count(Piece::Flat));
+    score += self.reserve_cap * i32::from(reserves.count(Piece::Cap));
+
+    for y in 0 .. size {
+      for x in 0 .. size {
+        let loc = Loc { x: x as u8, y: y as u8 };
+        let top = match game.top(loc) {
+          Some(top) => top,
+          None => continue,
+        };
+        let stack = game.stack(loc);
+        if stack.get(0) != player {
+          continue;
+        }
+        let center = center_distance(size, x) + center_distance(size, y);
+        score += match top {
+          Piece::Flat => self.flat,
+          Piece::Wall => self.wall,
+          Piece::Cap => self.cap + self.cap_center * center,
+        };
+        score += self.center * center;
+        for p in stack.into_iter().skip(1) {
+          score += if p == player { self.support } else { self.captive };
+        }
+      }
+    }
+
+    score + self.group * groups(game, player)
+  }
+}
+
+impl Evaluator for Handcrafted {
+  fn evaluate(&self, game: &Game) -> i32 {
+    let player = game.cur_player();
+    self.tempo + self.score(game, player) - self.score(game, player.opponent())
+  }
+}
+
+// Steps from the edge towards the center, i.e. 0 to 2 on a 5x5 board
+fn center_distance(size: usize, x: usize) -> i32 {
+  x.min(size - 1 - x) as i32
+}
+
+// Sum over connected groups of player's road pieces of the square of the rows
+// or columns they span beyond the first
+fn groups(game: &Game, player: Player) -> i32 {
+  let size = game.size();
+  let row_mask = (1u64 << size) - 1;
+  let mut pieces = game.road_pieces(player);
+  let mut total = 0;
+  while pieces != 0 {
+    let group = game.flood(pieces & pieces.wrapping_neg(), pieces);
+    pieces &= !group;
+    // Columns and rows the group has a piece in
+    let (mut columns, mut rows) = (0u64, 0u64);
+    for y in 0 .. size {
+      let row = (group >> (size * y)) & row_mask;
+      if row != 0 {
+        columns |= row;
+        rows |= 1 << y;
+      }
+    }
+    let extent = span(columns).max(span(rows));
+    total += extent * extent;
+  }
+  total
+}
+
+// Distance between the lowest and highest set bits
+#[inline]
+fn span(bits: u64) -> i32 {
+  (63 - bits.leading_zeros() - bits.trailing_zeros()) as i32
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Evaluator, Handcrafted};
+  use tps;
+
+  #[test]
+  fn symmetric() {
+    let eval = Handcrafted::default();
+    assert_eq!(eval.evaluate(&::game::new(5).unwrap()), eval.tempo);
+
+    // Swapping colours and the player to move gives the same score
+    let white = tps::parse("x5/x,1,x3/x,2,1C,x2/x2,21,x2/x5 2 3").unwrap();
+    let black = tps::parse("x5/x,2,x3/x,1,2C,x2/x2,12,x2/x5 1 3").unwrap();
+    assert_eq!(eval.evaluate(&white), eval.evaluate(&black));
+    let black_to_move = tps::parse("x5/x,1,x3/x,2,1C,x2/x2,21,x2/x5 1 3").unwrap();
+    assert_eq!(eval.evaluate(&black_to_move) - eval.tempo, -(eval.evaluate(&white) - eval.tempo));
+  }
+
+  #[test]
+  fn features() {
+    let eval = Handcrafted { tempo: 0, ..Handcrafted::default() };
+    let only = |w: Handcrafted, position: &str| w.evaluate(&tps::parse(position).unwrap());
+    let zero = Handcrafted {
+      flat: 0, wall: 0, cap: 0, reserve_flat: 0, reserve_cap: 0, captive: 0,
+      support: 0, center: 0, cap_center: 0, group: 0, tempo: 0,
+    };
+
+    // One flat each, white's in the middle
+    let position = "x5/x5/x2,1,x2/x5/2,x4 1 2";
+    assert_eq!(only(Handcrafted { flat: 1, ..zero }, position), 0);
+    assert_eq!(only(Handcrafted { center: 1, ..zero }, position), 4);
+    assert!(eval.evaluate(&tps::parse(position).unwrap()) > 0);
+
+    // White controls a stack with a black captive and a white support piece
+    let position = "x5/x5/x2,121,x2/x5/x5 1 3";
+    assert_eq!(only(Handcrafted { captive: 1, ..zero }, position), 1);
+    assert_eq!(only(Handcrafted { support: 1, ..zero }, position), 1);
+    assert_eq!(only(Handcrafted { reserve_flat: 1, ..zero }, position), -1);
+
+    // Groups count by extent, and walls don't connect
+    let position = "x5/x5/1,1,1,x2/x5/2,2S,2,x2 1 4";
+    assert_eq!(only(Handcrafted { group: 1, ..zero }, position), 4);
+    assert_eq!(only(Handcrafted { wall: 1, ..zero }, position), -1);
+  }
+
+  #[test]
+  fn closures() {
+    let eval = |g: &::game::Game| g.round() as i32;
+    assert_eq!(eval.evaluate(&::game::new(5).unwrap()), 1);
+  }
+}
@@ -1,4 +1,6 @@
 ﻿pub use ::bits::Game;
+use std::fmt;
+use std::str::FromStr;
 
 #[derive(Debug,Clone,Copy,PartialEq,Hash)]
 pub enum Player {
@@ -16,12 +18,12 @@ impl Player {
   }
 }
 
-#[derive(Debug,Clone,Copy,PartialEq,Hash)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
 pub enum Piece {
   Flat, Wall, Cap
 }
 
-#[derive(Debug,Clone,Copy,PartialEq,Hash)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
 pub enum Dir {
   Up,
   Down,
@@ -29,7 +31,7 @@ pub enum Dir {
   Right
 }
 
-#[derive(Debug,Clone,Copy,PartialEq,Hash)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
 pub struct Loc {
   pub x: u8,
   pub y: u8,
@@ -44,12 +46,114 @@ impl Loc {
       Dir::Right => Loc { x: self.x.wrapping_add(dist), y: self.y },
     }
   }
+
+  // Square that loc ends up on when a size x size board is transformed by sym
+  #[inline]
+  pub fn transform(self, sym: Symmetry, size: usize) -> Self {
+    // Work in doubled coordinates relative to the centre of the board, so the
+    // transform is just a rotation or reflection about the origin
+    let max = size as i32 - 1;
+    let (x, y) = sym.apply(2 * i32::from(self.x) - max, 2 * i32::from(self.y) - max);
+    Loc { x: ((x + max) / 2) as u8, y: ((y + max) / 2) as u8 }
+  }
+}
+
+impl Dir {
+  #[inline]
+  pub fn transform(self, sym: Symmetry) -> Self {
+    let (dx, dy) = match self {
+      Dir::Up => (0, 1),
+      Dir::Down => (0, -1),
+      Dir::Left => (-1, 0),
+      Dir::Right => (1, 0),
+    };
+    match sym.apply(dx, dy) {
+      (0, 1) => Dir::Up,
+      (0, -1) => Dir::Down,
+      (-1, 0) => Dir::Left,
+      _ => Dir::Right,
+    }
+  }
+}
+
+// The eight rotations and reflections of the board. Rotations are clockwise,
+// with a1 in the bottom left corner.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum Symmetry {
+  Identity,
+  Rotate90,
+  Rotate180,
+  Rotate270,
+  // Mirror left to right (a <-> e on 5x5)
+  FlipHorizontal,
+  // Mirror top to bottom (1 <-> 5 on 5x5)
+  FlipVertical,
+  // Mirror along the a1-e5 diagonal
+  Transpose,
+  // Mirror along the a5-e1 diagonal
+  AntiTranspose,
+}
+
+pub static SYMMETRIES : [Symmetry; 8] = [
+  Symmetry::Identity,
+  Symmetry::Rotate90,
+  Symmetry::Rotate180,
+  Symmetry::Rotate270,
+  Symmetry::FlipHorizontal,
+  Symmetry::FlipVertical,
+  Symmetry::Transpose,
+  Symmetry::AntiTranspose,
+];
+
+impl Symmetry {
+  // Transform that undoes this one
+  pub fn inverse(self) -> Self {
+    match self {
+      Symmetry::Rotate90 => Symmetry::Rotate270,
+      Symmetry::Rotate270 => Symmetry::Rotate90,
+      sym => sym,
+    }
+  }
+
+  // Apply the transform to a vector, without the translation that keeps
+  // squares on the board
+  #[inline]
+  fn apply(self, x: i32, y: i32) -> (i32, i32) {
+    match self {
+      Symmetry::Identity => (x, y),
+      Symmetry::Rotate90 => (y, -x),
+      Symmetry::Rotate180 => (-x, -y),
+      Symmetry::Rotate270 => (-y, x),
+      Symmetry::FlipHorizontal => (-x, y),
+      Symmetry::FlipVertical => (x, -y),
+      Symmetry::Transpose => (y, x),
+      Symmetry::AntiTranspose => (-y, -x),
+    }
+  }
 }
 
-#[derive(Debug,Clone,PartialEq,Hash)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
 pub enum Move {
   Place(Loc, Piece),
-  Move(Loc, Dir, u8, [u8; 7], bool),
+  Move(Loc, Dir, u8, [u8; 7]),
+}
+
+impl Move {
+  #[inline]
+  pub fn transform(self, sym: Symmetry, size: usize) -> Self {
+    match self {
+      Move::Place(loc, piece) => Move::Place(loc.transform(sym, size), piece),
+      Move::Move(loc, dir, range, drops) => Move::Move(loc.transform(sym, size), dir.transform(sym), range, drops),
+    }
+  }
+}
+
+// Returned by Game::execute, and holds what Game::undo needs to take the move back
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Undo {
+  pub m: Move,
+  // Whether a stack move flattened a wall
+  pub(crate) smash: bool,
 }
 
 #[derive(Debug,Clone,Copy,PartialEq)]
@@ -67,6 +171,10 @@ pub enum MoveValidity {
   MustPlaceFlatFirstRound,
   EndOutOfBounds,
   CarryLimit,
+  // A stack move that doesn't drop at least one piece on every square it crosses
+  InvalidDropCounts,
+  // The game has already ended. Game::validate doesn't check this, History::play does.
+  GameOver,
 }
 
 #[derive(Debug,Clone,Copy)]
@@ -88,6 +196,10 @@ impl Reserves {
     }
   }
 
+  pub fn with_counts(flats: u8, caps: u8) -> Self {
+    Reserves { flats, caps }
+  }
+
   pub fn empty(&self) -> bool {
     self.flats == 0 && self.caps == 0
   }
@@ -114,6 +226,86 @@ impl Reserves {
   }
 }
 
+// Flats added to Black's count when scoring a flat win. Stored in half flats
+// so that half point komi (i.e. 2.5) can be represented exactly.
+#[derive(Debug,Clone,Copy,PartialEq,Default)]
+pub struct Komi(u8);
+
+impl Komi {
+  pub fn from_half_flats(half_flats: u8) -> Self {
+    Komi(half_flats)
+  }
+
+  #[inline]
+  pub fn half_flats(self) -> u8 {
+    self.0
+  }
+}
+
+impl FromStr for Komi {
+  type Err = ();
+
+  // Parses komi in flats, i.e. "2" or "2.5"
+  fn from_str(s: &str) -> Result<Komi, ()> {
+    let (whole, half) = match s.find('.') {
+      Some(i) => match &s[i+1 ..] {
+        "0" => (&s[.. i], 0),
+        "5" => (&s[.. i], 1),
+        _ => return Err(()),
+      },
+      None => (s, 0),
+    };
+    match whole.parse::<u8>() {
+      Ok(n) if n < 128 => Ok(Komi(n * 2 + half)),
+      _ => Err(()),
+    }
+  }
+}
+
+impl fmt::Display for Komi {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.0 % 2 == 0 {
+      write!(f, "{}", self.0 / 2)
+    } else {
+      write!(f, "{}.5", self.0 / 2)
+    }
+  }
+}
+
+// Rules for a game. Rules::new gives the standard rules for a board size, which
+// can then be adjusted for variants, i.e.
+//   Rules { caps: 2, ..Rules::new(6).unwrap() }
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Rules {
+  pub size: usize,
+  // Pieces each player starts with
+  pub flats: u8,
+  pub caps: u8,
+  // Most pieces that can be picked up from a stack in one move
+  pub carry_limit: u8,
+  // Whether each player places one of their opponent's flats on their first turn
+  pub swap: bool,
+  pub komi: Komi,
+  // If a move completes roads for both players, whether the player who moved wins (the dragon
+  // clause) rather than the player to move next
+  pub dragon_clause: bool,
+}
+
+impl Rules {
+  pub fn new(size: usize) -> Option<Self> {
+    let reserves = Reserves::new(size)?;
+    Some(Rules {
+      size,
+      flats: reserves.count(Piece::Flat),
+      caps: reserves.count(Piece::Cap),
+      carry_limit: size as u8,
+      swap: true,
+      komi: Komi::default(),
+      dragon_clause: true,
+    })
+  }
+}
+
 #[derive(Debug,Clone,Copy,PartialEq)]
 pub enum Winner {
   Road(Player),
@@ -122,6 +314,24 @@ pub enum Winner {
   Draw,
 }
 
+// Pair of opposite edges connected by a road
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum RoadEdges {
+  // First rank to last rank
+  BottomTop,
+  // a file to last file
+  LeftRight,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Road {
+  pub player: Player,
+  pub edges: RoadEdges,
+  // For Game::roads, every square of the connected group that makes the road,
+  // in board order. For Game::shortest_road, a path from one edge to the other.
+  pub squares: Vec<Loc>,
+}
+
 pub fn new(size: usize) -> Option<::bits::Game> {
   Game::new(size)
 }
@@ -0,0 +1,196 @@
+use game::{Game, Move, MoveValidity, Rules, Undo};
+use ptn::{Ptn, Tag};
+use tps;
+
+// A game along with the moves played in it, so moves can be undone without the
+// caller keeping track of them. Undone moves are kept until a new move is played,
+// so they can be redone.
+#[derive(Debug,Clone)]
+pub struct History {
+  start: Game,
+  game: Game,
+  moves: Vec<Move>,
+  // Undo tokens for the moves currently applied to `game`
+  undos: Vec<Undo>,
+}
+
+impl History {
+  pub fn new(game: Game) -> Self {
+    History { start: game.clone(), game, moves: Vec::new(), undos: Vec::new() }
+  }
+
+  #[inline]
+  pub fn game(&self) -> &Game { &self.game }
+
+  // Position the history started from
+  #[inline]
+  pub fn start(&self) -> &Game { &self.start }
+
+  #[inline]
+  pub fn ply(&self) -> usize { self.undos.len() }
+
+  // Number of moves recorded, including ones that have been undone
+  #[inline]
+  pub fn len(&self) -> usize { self.moves.len() }
+
+  #[inline]
+  pub fn is_empty(&self) -> bool { self.moves.is_empty() }
+
+  // Moves leading up to the current position
+  #[inline]
+  pub fn moves(&self) -> &[Move] { &self.moves[.. self.ply()] }
+
+  // Play a move from the current position, dropping any undone moves. Moves
+  // after the end of the game aren't allowed.
+  pub fn play(&mut self, m: Move) -> Result<(), MoveValidity> {
+    if self.game.status().is_some() {
+      return Err(MoveValidity::GameOver);
+    }
+    match self.game.validate(&m) {
+      MoveValidity::Valid => {},
+      invalid => return Err(invalid),
+    }
+    self.moves.truncate(self.undos.len());
+    self.undos.push(self.game.execute(&m));
+    self.moves.push(m);
+    Ok(())
+  }
+
+  pub fn undo(&mut self) -> bool {
+    match self.undos.pop() {
+      Some(undo) => { self.game.undo(&undo); true },
+      None => false,
+    }
+  }
+
+  pub fn redo(&mut self) -> bool {
+    match self.moves.get(self.undos.len()) {
+      Some(m) => { self.undos.push(self.game.execute(m)); true },
+      None => false,
+    }
+  }
+
+  // Undo or redo moves until `ply` moves are applied. Returns false, without
+  // changing anything, if fewer than `ply` moves have been recorded.
+  pub fn goto(&mut self, ply: usize) -> bool {
+    if ply > self.moves.len() {
+      return false;
+    }
+    while self.ply() > ply { self.undo(); }
+    while self.ply() < ply { self.redo(); }
+    true
+  }
+
+  // PTN for the moves up to the current position
+  pub fn to_ptn(&self) -> Ptn {
+    let mut ptn = Ptn::from_moves(self.game.size(), self.moves());
+    ptn.komi = self.game.komi();
+    ptn.result = self.game.status();
+
+    let rules = self.start.rules();
+    if let Some(standard) = Rules::new(rules.size) {
+      if rules.flats != standard.flats {
+        ptn.tags.push(Tag { name: "flats".to_string(), value: rules.flats.to_string() });
+      }
+      if rules.caps != standard.caps {
+        ptn.tags.push(Tag { name: "caps".to_string(), value: rules.caps.to_string() });
+      }
+    }
+    let start = tps::to_string(&self.start);
+    let fresh = Game::with_rules(*self.start.rules()).map(|g| tps::to_string(&g));
+    if fresh.as_ref() != Some(&start) {
+      ptn.tags.push(Tag { name: "tps".to_string(), value: start });
+    }
+    ptn
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::History;
+  use game::{self, Loc, Move, Piece, Player, Rules, Winner, MoveValidity};
+  use ptn;
+  use tps;
+
+  fn moves() -> Vec<Move> {
+    ["a5", "a1", "b1", "a2", "c1", "Cb2", "d1", "b2<", "e1"].iter()
+      .map(|m| ptn::parse_move(m).unwrap())
+      .collect()
+  }
+
+  #[test]
+  fn undo_redo() {
+    let mut h = History::new(game::new(5).unwrap());
+    let mut positions = vec![h.game().to_string()];
+    for m in moves() {
+      h.play(m).unwrap();
+      positions.push(h.game().to_string());
+    }
+    assert_eq!(h.ply(), 9);
+    // e1 finished the game with a road
+    assert_eq!(h.play(Move::Place(Loc { x: 4, y: 4 }, Piece::Flat)), Err(MoveValidity::GameOver));
+    assert_eq!(h.len(), 9);
+
+    for ply in (0 .. 9).rev() {
+      assert!(h.undo());
+      assert_eq!(h.game().to_string(), positions[ply]);
+    }
+    assert!(!h.undo());
+
+    assert!(h.goto(5));
+    assert_eq!(h.game().to_string(), positions[5]);
+    assert!(h.redo());
+    assert_eq!(h.moves().len(), 6);
+    assert!(!h.goto(10));
+    assert!(h.goto(9));
+    assert!(!h.redo());
+    assert_eq!(h.game().to_string(), positions[9]);
+
+    // Playing a new move drops the undone ones
+    h.goto(2);
+    h.play(Move::Place(Loc { x: 1, y: 1 }, Piece::Wall)).unwrap();
+    assert_eq!((h.ply(), h.len()), (3, 3));
+    assert_eq!(h.play(Move::Place(Loc { x: 1, y: 1 }, Piece::Flat)), Err(MoveValidity::SquareOccupied));
+  }
+
+  #[test]
+  fn smash_undo() {
+    let mut h = History::new(tps::parse("x5/x5/x5/x5/1C,2S,x3 1 3").unwrap());
+    h.play(ptn::parse_move("a1>").unwrap()).unwrap();
+    assert_eq!(h.game().to_string(), "x5/x5/x5/x5/x,21C,x3 2 3");
+    h.undo();
+    assert_eq!(h.game().to_string(), "x5/x5/x5/x5/1C,2S,x3 1 3");
+  }
+
+  #[test]
+  fn export() {
+    let mut h = History::new(game::new(5).unwrap());
+    for m in moves() {
+      h.play(m).unwrap();
+    }
+    let ptn = h.to_ptn();
+    assert_eq!(ptn.result, Some(Winner::Road(Player::White)));
+    assert_eq!(ptn.to_string(), "[Size \"5\"]\n\n1. a5 a1\n2. b1 a2\n3. c1 Cb2\n4. d1 b2<\n5. e1\nR-0\n");
+
+    h.goto(4);
+    let ptn = History::new(h.game().clone()).to_ptn();
+    assert_eq!(ptn.tags[1].value, "2,x4/x5/x5/2,x4/1,1,x3 1 3");
+
+    // Black to move, with fewer pieces than usual
+    let rules = Rules { flats: 15, caps: 0, ..Rules::new(5).unwrap() };
+    let start = tps::parse_with_rules("2,x4/x5/x5/x5/1,x4 2 2", rules).unwrap();
+    let mut h = History::new(start);
+    for m in ["e1", "e5", "d1"].iter() {
+      h.play(ptn::parse_move(m).unwrap()).unwrap();
+    }
+    let written = h.to_ptn().to_string();
+    assert_eq!(written, "[Size \"5\"]\n[Flats \"15\"]\n[Caps \"0\"]\n[TPS \"2,x4/x5/x5/x5/1,x4 2 2\"]\n\n2. -- e1\n3. e5 d1\n");
+    let parsed = ptn::parse(&written).unwrap();
+    let mut g = parsed.start().unwrap();
+    assert_eq!(g.reserves(Player::White).count(Piece::Flat), 14);
+    for m in parsed.moves.iter() {
+      g.execute(&m.m);
+    }
+    assert_eq!(tps::to_string(&g), tps::to_string(h.game()));
+  }
+}
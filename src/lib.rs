@@ -2,11 +2,9 @@
 #![feature(const_fn)]
 extern crate test;
 
-#[macro_use]
-extern crate nom;
 extern crate time;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "sqlite"))]
 extern crate sqlite;
 
 pub mod bits;
@@ -14,7 +12,19 @@ pub mod game;
 pub mod ptn;
 pub mod tps;
 pub mod playtak;
+pub mod client;
 pub mod tables;
+pub mod history;
+pub mod tinue;
+pub mod eval;
+pub mod engine;
+pub mod mcts;
+pub mod book;
+pub mod annotate;
+#[cfg(feature = "sqlite")]
+pub mod archive;
+
+mod rng;
 
 mod fnv64 {
   use std::hash::Hasher;
@@ -66,7 +76,15 @@ mod fnv64 {
 
   impl Hasher for Fnv64 {
     fn finish(&self) -> u64 {
-      self.hash
+      // FNV only mixes the last byte written with a single multiply, so hashes
+      // that differ just in a trailing length or player byte collide easily when
+      // they're XORed together. Finish with the murmur3 mixer to spread them out.
+      let mut h = self.hash;
+      h ^= h >> 33;
+      h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+      h ^= h >> 33;
+      h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+      h ^ (h >> 33)
     }
 
     fn write(&mut self, bytes: &[u8]) {
@@ -0,0 +1,367 @@
+use game::{Game, Move, Player, Undo, Winner};
+use rng::Rng;
+
+// Evaluates positions at the leaves of the search tree. Values are from the
+// point of view of the player to move, from -1 (loss) to 1 (win).
+pub trait LeafEvaluator {
+  // Value of game, and prior probabilities for the legal moves, which are
+  // pushed onto priors in the same order as moves. Priors don't need to sum to 1.
+  fn evaluate(&mut self, game: &Game, moves: &[Move], priors: &mut Vec<f32>) -> f32;
+}
+
+// Uniform priors and the result of a random playout
+#[derive(Debug,Clone)]
+pub struct RandomRollout {
+  rng: Rng,
+  // Playouts that go on longer than this count as draws
+  pub max_plies: u32,
+}
+
+impl RandomRollout {
+  pub fn new(seed: u64) -> Self {
+    RandomRollout { rng: Rng::new(seed), max_plies: 200 }
+  }
+}
+
+impl LeafEvaluator for RandomRollout {
+  fn evaluate(&mut self, game: &Game, moves: &[Move], priors: &mut Vec<f32>) -> f32 {
+    priors.extend(moves.iter().map(|_| 1.0));
+    let player = game.cur_player();
+    let mut g = game.clone();
+    let mut playout = Vec::new();
+    for _ in 0 .. self.max_plies {
+      if let Some(w) = g.status() {
+        return outcome(w, player);
+      }
+      playout.clear();
+      playout.extend(g.moves());
+      let m = playout[self.rng.below(playout.len())];
+      g.execute(&m);
+    }
+    g.status().map_or(0.0, |w| outcome(w, player))
+  }
+}
+
+fn outcome(w: Winner, player: Player) -> f32 {
+  match w {
+    Winner::Road(p) | Winner::Flat(p) | Winner::Other(p) => if p == player { 1.0 } else { -1.0 },
+    Winner::Draw => 0.0,
+  }
+}
+
+#[derive(Debug,Clone,Copy)]
+pub struct Config {
+  // Exploration constant for PUCT
+  pub c_puct: f32,
+  // Dirichlet noise mixed into the root priors, for self-play. A weight of 0
+  // turns it off.
+  pub noise_alpha: f32,
+  pub noise_weight: f32,
+  pub seed: u64,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config { c_puct: 1.5, noise_alpha: 0.3, noise_weight: 0.0, seed: 1 }
+  }
+}
+
+#[derive(Debug,Clone)]
+struct Node {
+  // Move leading to this node, None for the root
+  m: Option<Move>,
+  children: Vec<usize>,
+  visits: u32,
+  // Sum of values from the point of view of the player who made m
+  value_sum: f32,
+  prior: f32,
+  expanded: bool,
+}
+
+impl Node {
+  fn new(m: Option<Move>, prior: f32) -> Self {
+    Node { m, children: Vec::new(), visits: 0, value_sum: 0.0, prior, expanded: false }
+  }
+}
+
+// PUCT search. The tree is kept between moves, so after advance() the subtree
+// for the move played is reused.
+pub struct Mcts<E> {
+  game: Game,
+  eval: E,
+  config: Config,
+  rng: Rng,
+  nodes: Vec<Node>,
+  noised: bool,
+}
+
+impl<E: LeafEvaluator> Mcts<E> {
+  pub fn new(game: Game, eval: E, config: Config) -> Self {
+    Mcts {
+      game,
+      eval,
+      config,
+      rng: Rng::new(config.seed),
+      nodes: vec![Node::new(None, 1.0)],
+      noised: false,
+    }
+  }
+
+  // Position at the root of the tree
+  #[inline]
+  pub fn game(&self) -> &Game { &self.game }
+
+  #[inline]
+  pub fn evaluator(&mut self) -> &mut E { &mut self.eval }
+
+  // Number of playouts through the root, including ones from earlier moves
+  #[inline]
+  pub fn root_visits(&self) -> u32 { self.nodes[0].visits }
+
+  // Average value of the root for the player to move
+  pub fn value(&self) -> f32 {
+    let root = &self.nodes[0];
+    if root.visits == 0 { 0.0 } else { -root.value_sum / root.visits as f32 }
+  }
+
+  // Run playouts until the root has been visited `visits` times in total
+  pub fn search(&mut self, visits: u32) {
+    if self.game.status().is_some() {
+      return;
+    }
+    while self.nodes[0].visits < visits {
+      self.playout();
+    }
+  }
+
+  fn playout(&mut self) {
+    // The root's children exist after its first expansion, or straight away
+    // when advance() kept a subtree
+    if !self.noised && self.nodes[0].expanded && self.config.noise_weight > 0.0 {
+      self.add_noise();
+    }
+    let mut path = vec![0];
+    let mut undos : Vec<Undo> = Vec::new();
+    let mut idx = 0;
+    while self.nodes[idx].expanded && !self.nodes[idx].children.is_empty() {
+      idx = self.select_child(idx);
+      path.push(idx);
+      undos.push(self.game.execute(&self.nodes[idx].m.unwrap()));
+    }
+
+    let value = match self.game.status() {
+      Some(w) => outcome(w, self.game.cur_player()),
+      None => self.expand(idx),
+    };
+
+    // Each node's value is for the player who moved into it, i.e. the opponent
+    // of the player to move there
+    let mut value = -value;
+    for &i in path.iter().rev() {
+      let node = &mut self.nodes[i];
+      node.visits += 1;
+      node.value_sum += value;
+      value = -value;
+    }
+    while let Some(undo) = undos.pop() {
+      self.game.undo(&undo);
+    }
+  }
+
+  fn select_child(&self, idx: usize) -> usize {
+    let node = &self.nodes[idx];
+    let sqrt_n = (node.visits as f32).sqrt();
+    let score = |c: &Node| {
+      let q = if c.visits == 0 { 0.0 } else { c.value_sum / c.visits as f32 };
+      q + self.config.c_puct * c.prior * sqrt_n / (1.0 + c.visits as f32)
+    };
+    let mut best = node.children[0];
+    let mut best_score = score(&self.nodes[best]);
+    for &c in node.children[1..].iter() {
+      let s = score(&self.nodes[c]);
+      if s > best_score {
+        best = c;
+        best_score = s;
+      }
+    }
+    best
+  }
+
+  // Add children for every legal move and return the evaluator's value
+  fn expand(&mut self, idx: usize) -> f32 {
+    let moves : Vec<Move> = self.game.moves().collect();
+    let mut priors = Vec::with_capacity(moves.len());
+    let value = self.eval.evaluate(&self.game, &moves, &mut priors);
+    let sum : f32 = priors.iter().sum();
+    for (i, m) in moves.into_iter().enumerate() {
+      let prior = if sum > 0.0 { priors[i] / sum } else { 0.0 };
+      let child = self.nodes.len();
+      self.nodes[idx].children.push(child);
+      self.nodes.push(Node::new(Some(m), prior));
+    }
+    self.nodes[idx].expanded = true;
+    value
+  }
+
+  fn add_noise(&mut self) {
+    let children = self.nodes[0].children.clone();
+    let noise = self.rng.dirichlet(f64::from(self.config.noise_alpha), children.len());
+    let w = self.config.noise_weight;
+    for (&c, &n) in children.iter().zip(noise.iter()) {
+      let node = &mut self.nodes[c];
+      node.prior = (1.0 - w) * node.prior + w * n as f32;
+    }
+    self.noised = true;
+  }
+
+  // Visit counts of the moves at the root, i.e. as a policy target for training
+  pub fn visit_counts(&self) -> Vec<(Move, u32)> {
+    self.nodes[0].children.iter()
+      .map(|&c| (self.nodes[c].m.unwrap(), self.nodes[c].visits))
+      .collect()
+  }
+
+  // Most visited move at the root
+  pub fn best_move(&self) -> Option<Move> {
+    self.nodes[0].children.iter()
+      .max_by_key(|&&c| self.nodes[c].visits)
+      .map(|&c| self.nodes[c].m.unwrap())
+  }
+
+  // Pick a move with probability proportional to visits^(1/temperature). A
+  // temperature of 0 picks the most visited move.
+  pub fn select_move(&mut self, temperature: f32) -> Option<Move> {
+    if temperature <= 0.0 {
+      return self.best_move();
+    }
+    let weights : Vec<f64> = self.nodes[0].children.iter()
+      .map(|&c| f64::from(self.nodes[c].visits).powf(1.0 / f64::from(temperature)))
+      .collect();
+    let total : f64 = weights.iter().sum();
+    if total <= 0.0 {
+      return self.best_move();
+    }
+    let mut x = self.rng.next_f64() * total;
+    for (&c, &w) in self.nodes[0].children.iter().zip(weights.iter()) {
+      if x < w {
+        return self.nodes[c].m;
+      }
+      x -= w;
+    }
+    self.best_move()
+  }
+
+  // Play m at the root, keeping the part of the tree below it
+  pub fn advance(&mut self, m: &Move) {
+    let child = self.nodes[0].children.iter().cloned().find(|&c| self.nodes[c].m == Some(*m));
+    self.game.execute(m);
+    self.noised = false;
+
+    let mut nodes = Vec::new();
+    if let Some(child) = child {
+      // Copy the subtree into a fresh arena, so the rest of the tree is freed
+      let mut stack = vec![(child, 0)];
+      nodes.push(self.nodes[child].clone());
+      while let Some((old, new)) = stack.pop() {
+        let children = self.nodes[old].children.clone();
+        let mut new_children = Vec::with_capacity(children.len());
+        for c in children {
+          new_children.push(nodes.len());
+          stack.push((c, nodes.len()));
+          nodes.push(self.nodes[c].clone());
+        }
+        nodes[new].children = new_children;
+      }
+      nodes[0].m = None;
+    } else {
+      nodes.push(Node::new(None, 1.0));
+    }
+    self.nodes = nodes;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Config, LeafEvaluator, Mcts, RandomRollout};
+  use game::{Game, Loc, Move};
+  use tps;
+
+  #[test]
+  fn road_in_one() {
+    let g = tps::parse("x5/x5/2,2,x3/x5/1,1,1,1,x 1 5").unwrap();
+    let mut mcts = Mcts::new(g, RandomRollout::new(3), Config::default());
+    mcts.search(2000);
+    // Either e1 or Ce1 wins
+    let best = mcts.best_move().unwrap();
+    match best {
+      Move::Place(loc, _) => assert_eq!(loc, Loc { x: 4, y: 0 }),
+      m => panic!("{:?}", m),
+    }
+    assert!(mcts.value() > 0.5);
+    assert_eq!(mcts.select_move(0.0), Some(best));
+  }
+
+  #[test]
+  fn reuse_subtree() {
+    let mut mcts = Mcts::new(::game::new(4).unwrap(), RandomRollout::new(5), Config::default());
+    mcts.search(500);
+    assert_eq!(mcts.root_visits(), 500);
+    let counts = mcts.visit_counts();
+    assert_eq!(counts.len(), 16);
+    assert_eq!(counts.iter().map(|&(_, n)| n).sum::<u32>(), 499);
+
+    let m = mcts.select_move(1.0).unwrap();
+    let visits = counts.iter().find(|&&(c, _)| c == m).unwrap().1;
+    mcts.advance(&m);
+    assert_eq!(mcts.root_visits(), visits);
+    assert_eq!(mcts.game().round(), 1);
+    mcts.search(visits + 100);
+    assert_eq!(mcts.root_visits(), visits + 100);
+
+    // A move that was never expanded starts a fresh tree
+    let m = mcts.visit_counts().into_iter().find(|&(_, n)| n == 0).map(|(m, _)| m);
+    if let Some(m) = m {
+      mcts.advance(&m);
+      assert_eq!(mcts.root_visits(), 0);
+    }
+  }
+
+  // Puts all the prior on the first move in the list
+  struct FirstMove;
+
+  impl LeafEvaluator for FirstMove {
+    fn evaluate(&mut self, _: &Game, moves: &[Move], priors: &mut Vec<f32>) -> f32 {
+      priors.extend((0 .. moves.len()).map(|i| if i == 0 { 1.0 } else { 0.0 }));
+      0.0
+    }
+  }
+
+  #[test]
+  fn priors_and_noise() {
+    let g = ::game::new(5).unwrap();
+    let first = g.moves().next();
+    let mut mcts = Mcts::new(g.clone(), FirstMove, Config::default());
+    mcts.search(50);
+    assert_eq!(mcts.best_move(), first);
+    assert_eq!(mcts.visit_counts()[0].1, 49);
+
+    // With noise, other moves get explored too
+    let config = Config { noise_weight: 0.25, seed: 11, ..Config::default() };
+    let mut mcts = Mcts::new(g, FirstMove, config);
+    mcts.search(200);
+    let counts = mcts.visit_counts();
+    assert!(counts[0].1 > 100);
+    assert!(counts[1..].iter().filter(|&&(_, n)| n > 0).count() > 1);
+
+    // The kept subtree gets fresh noise at its root after advance
+    let m = mcts.best_move().unwrap();
+    mcts.advance(&m);
+    let priors = |mcts: &Mcts<FirstMove>| -> Vec<f32> {
+      mcts.nodes[0].children.iter().map(|&c| mcts.nodes[c].prior).collect()
+    };
+    let before = priors(&mcts);
+    assert!(!before.is_empty());
+    mcts.search(mcts.root_visits() + 1);
+    assert_ne!(priors(&mcts), before);
+  }
+}
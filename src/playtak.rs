@@ -1,10 +1,14 @@
-use game::{self, Move, Loc, Piece, Dir, Player};
+use std::fmt;
+use std::str::FromStr;
+use game::{self, Move, Loc, Piece, Dir, Player, Komi, Reserves, Winner};
 
 #[derive(Debug)]
 pub enum ErrorType {
   InvalidChar(char),
   InvalidMoveSquares(Loc,Loc),
   EndOfFile,
+  // A protocol line that is unknown or doesn't have the expected fields
+  InvalidMessage(String),
 }
 
 #[derive(Debug)]
@@ -16,7 +20,7 @@ pub enum ParseResult<'a, T> {
 #[inline]
 fn is_ws(c: char) -> bool {
   match c {
-    ' ' | '\t' | '\n' | 'r' => true,
+    ' ' | '\t' | '\n' | '\r' => true,
     _ => false,
   }
 }
@@ -113,7 +117,7 @@ pub fn parse_move(input: &str) -> Result<(Move, &str),ErrorType> {
         return Err(InvalidMoveSquares(start,end));
       };
       let (dropcounts, input) = parse_dropcounts(input, range)?;
-      Ok((Move::Move(start, dir, range, dropcounts, false), input))
+      Ok((Move::Move(start, dir, range, dropcounts), input))
     },
     Some(c) => Err(InvalidChar(c)),
     None    => Err(EndOfFile),
@@ -141,6 +145,30 @@ pub fn parse_moves(input: &str) -> Result<(Vec<Move>, &str), ErrorType> {
   Ok((moves, res.1))
 }
 
+// Move in the notation the playtak server uses, i.e. "P C3 W" or "M A1 A3 1 2"
+pub fn to_string(m: &Move) -> String {
+  let square = |loc: Loc| format!("{}{}", (loc.x + b'A') as char, loc.y + 1);
+  match *m {
+    Move::Place(loc, Piece::Flat) => format!("P {}", square(loc)),
+    Move::Place(loc, Piece::Wall) => format!("P {} W", square(loc)),
+    Move::Place(loc, Piece::Cap) => format!("P {} C", square(loc)),
+    Move::Move(loc, dir, range, drops) => {
+      let (dx, dy) = match dir {
+        Dir::Up => (0, range as i8),
+        Dir::Down => (0, -(range as i8)),
+        Dir::Left => (-(range as i8), 0),
+        Dir::Right => (range as i8, 0),
+      };
+      let end = Loc { x: (loc.x as i8 + dx) as u8, y: (loc.y as i8 + dy) as u8 };
+      let mut res = format!("M {} {}", square(loc), square(end));
+      for drop in drops.iter().take(range as usize) {
+        res.push_str(&format!(" {}", drop));
+      }
+      res
+    },
+  }
+}
+
 pub fn parse_result(input: &str) -> Result<(game::Winner, &str), ErrorType> {
   use self::ErrorType::*;
   let mut chars = input.chars();
@@ -230,95 +258,612 @@ pub fn parse_result(input: &str) -> Result<(game::Winner, &str), ErrorType> {
   Ok((res, chars.as_str()))
 }
 
+// Result as the server writes it, i.e. "R-0" or "1/2-1/2"
+pub fn result_to_string(w: Winner) -> &'static str {
+  match w {
+    Winner::Road(Player::White) => "R-0",
+    Winner::Road(Player::Black) => "0-R",
+    Winner::Flat(Player::White) => "F-0",
+    Winner::Flat(Player::Black) => "0-F",
+    Winner::Other(Player::White) => "1-0",
+    Winner::Other(Player::Black) => "0-1",
+    Winner::Draw => "1/2-1/2",
+  }
+}
+
+// Settings for a game, as sent in seeks. Times are in seconds.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Seek {
+  pub size: usize,
+  pub time: u32,
+  pub increment: u32,
+  // Color the seeker wants to play, or None for either
+  pub color: Option<Player>,
+  pub komi: Komi,
+  pub flats: u8,
+  pub caps: u8,
+  pub rated: bool,
+  pub tournament: bool,
+}
+
+impl Seek {
+  // Seek with the standard piece counts for size, or None if the size isn't
+  // supported
+  pub fn new(size: usize, time: u32, increment: u32) -> Option<Self> {
+    let reserves = Reserves::new(size)?;
+    Some(Seek {
+      size,
+      time,
+      increment,
+      color: None,
+      komi: Komi::default(),
+      flats: reserves.count(Piece::Flat),
+      caps: reserves.count(Piece::Cap),
+      rated: true,
+      tournament: false,
+    })
+  }
+}
+
+// Lines sent by the playtak server
+#[derive(Debug,Clone,PartialEq)]
+pub enum ServerMessage {
+  // "Welcome!"
+  Welcome,
+  LoginOrRegister,
+  // Successful login, with the name logged in as
+  LoggedIn(String),
+  Ok,
+  Nok,
+  SeekNew(u32, String, Seek),
+  SeekRemove(u32, String, Seek),
+  GameStart {
+    game: u32,
+    size: usize,
+    white: String,
+    black: String,
+    // Color of the player receiving the message
+    color: Player,
+    time: u32,
+    komi: Komi,
+    flats: u8,
+    caps: u8,
+  },
+  Move(u32, Move),
+  // Time left for white and black, in seconds
+  Time(u32, u32, u32),
+  Over(u32, Winner),
+  Abandoned(u32),
+  OfferDraw(u32),
+  RemoveDraw(u32),
+  RequestUndo(u32),
+  RemoveUndo(u32),
+  Undo(u32),
+  // Sender and text
+  Shout(String, String),
+  Online(u32),
+  Message(String),
+  Error(String),
+}
+
+// Lines sent by a client
+#[derive(Debug,Clone,PartialEq)]
+pub enum ClientMessage {
+  Client(String),
+  // Name and password
+  Login(String, String),
+  LoginGuest,
+  Seek(Seek),
+  Accept(u32),
+  Move(u32, Move),
+  OfferDraw(u32),
+  RemoveDraw(u32),
+  RequestUndo(u32),
+  RemoveUndo(u32),
+  Resign(u32),
+  Shout(String),
+  Ping,
+  Quit,
+}
+
+// Splits a line into words, with errors that point back at the whole line
+struct Words<'a> {
+  line: &'a str,
+  rest: &'a str,
+}
+
+impl<'a> Words<'a> {
+  fn new(line: &'a str) -> Self {
+    let line = line.trim_end_matches(|c| c == '\r' || c == '\n');
+    Words { line, rest: line }
+  }
+
+  fn error(&self) -> ErrorType {
+    ErrorType::InvalidMessage(self.line.to_string())
+  }
+
+  fn next(&mut self) -> Result<&'a str, ErrorType> {
+    let rest = ws(self.rest);
+    let end = rest.find(is_ws).unwrap_or(rest.len());
+    if end == 0 {
+      return Err(self.error());
+    }
+    self.rest = &rest[end ..];
+    Ok(&rest[.. end])
+  }
+
+  fn number<T: FromStr>(&mut self) -> Result<T, ErrorType> {
+    let word = self.next()?;
+    word.parse().map_err(|_| self.error())
+  }
+
+  fn expect(&mut self, word: &str) -> Result<(), ErrorType> {
+    if self.next()? == word { Ok(()) } else { Err(self.error()) }
+  }
+
+  fn is_empty(&self) -> bool {
+    ws(self.rest).is_empty()
+  }
+
+  // Everything left on the line, after a single separating space
+  fn text(&mut self) -> &'a str {
+    let rest = self.rest;
+    self.rest = "";
+    if rest.starts_with(' ') { &rest[1 ..] } else { rest }
+  }
+
+  fn end(&self) -> Result<(), ErrorType> {
+    if self.is_empty() { Ok(()) } else { Err(self.error()) }
+  }
+
+  // A move in playtak notation, which must be the rest of the line
+  fn game_move(&mut self) -> Result<Move, ErrorType> {
+    let (m, rest) = parse_move(ws(self.rest)).map_err(|_| self.error())?;
+    self.rest = rest;
+    self.end()?;
+    Ok(m)
+  }
+}
+
+// Game number from a "Game#N" word
+fn game_number(words: &Words, word: &str) -> Result<u32, ErrorType> {
+  word["Game#".len() ..].parse().map_err(|_| words.error())
+}
+
+// Seek fields after the seek number and name. Older servers stop after the
+// color, so everything after it is optional.
+fn parse_seek(words: &mut Words) -> Result<Seek, ErrorType> {
+  let size = words.number()?;
+  let time = words.number()?;
+  let increment = words.number()?;
+  let mut seek = Seek::new(size, time, increment).ok_or_else(|| words.error())?;
+  if words.is_empty() {
+    return Ok(seek);
+  }
+  seek.color = match words.next()? {
+    "W" => Some(Player::White),
+    "B" => Some(Player::Black),
+    "A" => None,
+    _ => return Err(words.error()),
+  };
+  if words.is_empty() {
+    return Ok(seek);
+  }
+  seek.komi = Komi::from_half_flats(words.number()?);
+  seek.flats = words.number()?;
+  seek.caps = words.number()?;
+  if words.is_empty() {
+    return Ok(seek);
+  }
+  seek.rated = words.number::<u8>()? == 0;
+  seek.tournament = words.number::<u8>()? != 0;
+  Ok(seek)
+}
+
+fn write_seek(f: &mut fmt::Formatter, seek: &Seek) -> fmt::Result {
+  let color = match seek.color {
+    Some(Player::White) => "W",
+    Some(Player::Black) => "B",
+    None => "A",
+  };
+  write!(f, "{} {} {} {} {} {} {} {} {}",
+    seek.size, seek.time, seek.increment, color, seek.komi.half_flats(),
+    seek.flats, seek.caps, u8::from(!seek.rated), u8::from(seek.tournament))
+}
+
+pub fn parse_server_message(line: &str) -> Result<ServerMessage, ErrorType> {
+  use self::ServerMessage::*;
+  let mut words = Words::new(line);
+  let msg = match words.next()? {
+    "Welcome!" => Welcome,
+    "Welcome" => {
+      let name = words.next()?;
+      if !name.ends_with('!') {
+        return Err(words.error());
+      }
+      LoggedIn(name[.. name.len() - 1].to_string())
+    },
+    "Login" => {
+      words.expect("or")?;
+      words.expect("Register")?;
+      LoginOrRegister
+    },
+    "OK" => Ok,
+    "NOK" => Nok,
+    "Seek" => {
+      let new = match words.next()? {
+        "new" => true,
+        "remove" => false,
+        _ => return Err(words.error()),
+      };
+      let id = words.number()?;
+      let name = words.next()?.to_string();
+      let seek = parse_seek(&mut words)?;
+      if new { SeekNew(id, name, seek) } else { SeekRemove(id, name, seek) }
+    },
+    "Game" => {
+      words.expect("Start")?;
+      let game = words.number()?;
+      let size = words.number()?;
+      let white = words.next()?.to_string();
+      words.expect("vs")?;
+      let black = words.next()?.to_string();
+      let color = match words.next()? {
+        "white" => Player::White,
+        "black" => Player::Black,
+        _ => return Err(words.error()),
+      };
+      let time = words.number()?;
+      let reserves = Reserves::new(size).ok_or_else(|| words.error())?;
+      let (komi, flats, caps) = if words.is_empty() {
+        (Komi::default(), reserves.count(Piece::Flat), reserves.count(Piece::Cap))
+      } else {
+        (Komi::from_half_flats(words.number()?), words.number()?, words.number()?)
+      };
+      // Any fields after these, from newer servers, are ignored
+      return Result::Ok(GameStart { game, size, white, black, color, time, komi, flats, caps });
+    },
+    word if word.starts_with("Game#") => {
+      let game = game_number(&words, word)?;
+      let args = words.rest;
+      match words.next()? {
+        "P" | "M" => {
+          // Back up so the move parser sees the P or M
+          words.rest = args;
+          Move(game, words.game_move()?)
+        },
+        "Time" => Time(game, words.number()?, words.number()?),
+        "Over" => {
+          let (w, rest) = parse_result(words.next()?).map_err(|_| words.error())?;
+          if !rest.is_empty() {
+            return Err(words.error());
+          }
+          Over(game, w)
+        },
+        // Newer servers add who quit, i.e. "Abandoned. alice quit"
+        "Abandoned" | "Abandoned." => {
+          words.text();
+          Abandoned(game)
+        },
+        "OfferDraw" => OfferDraw(game),
+        "RemoveDraw" => RemoveDraw(game),
+        "RequestUndo" => RequestUndo(game),
+        "RemoveUndo" => RemoveUndo(game),
+        "Undo" => Undo(game),
+        _ => return Err(words.error()),
+      }
+    },
+    "Shout" => {
+      let name = words.next()?;
+      if !name.starts_with('<') || !name.ends_with('>') || name.len() < 2 {
+        return Err(words.error());
+      }
+      Shout(name[1 .. name.len() - 1].to_string(), words.text().to_string())
+    },
+    "Online" => Online(words.number()?),
+    "Message" => Message(words.text().to_string()),
+    "Error" => Error(words.text().to_string()),
+    _ => return Err(words.error()),
+  };
+  words.end()?;
+  Result::Ok(msg)
+}
+
+impl fmt::Display for ServerMessage {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use self::ServerMessage::*;
+    match *self {
+      Welcome => write!(f, "Welcome!"),
+      LoginOrRegister => write!(f, "Login or Register"),
+      LoggedIn(ref name) => write!(f, "Welcome {}!", name),
+      Ok => write!(f, "OK"),
+      Nok => write!(f, "NOK"),
+      SeekNew(id, ref name, ref seek) => {
+        write!(f, "Seek new {} {} ", id, name)?;
+        write_seek(f, seek)
+      },
+      SeekRemove(id, ref name, ref seek) => {
+        write!(f, "Seek remove {} {} ", id, name)?;
+        write_seek(f, seek)
+      },
+      GameStart { game, size, ref white, ref black, color, time, komi, flats, caps } => {
+        let color = if color == Player::White { "white" } else { "black" };
+        write!(f, "Game Start {} {} {} vs {} {} {} {} {} {}",
+          game, size, white, black, color, time, komi.half_flats(), flats, caps)
+      },
+      Move(game, ref m) => write!(f, "Game#{} {}", game, to_string(m)),
+      Time(game, white, black) => write!(f, "Game#{} Time {} {}", game, white, black),
+      Over(game, w) => write!(f, "Game#{} Over {}", game, result_to_string(w)),
+      Abandoned(game) => write!(f, "Game#{} Abandoned", game),
+      OfferDraw(game) => write!(f, "Game#{} OfferDraw", game),
+      RemoveDraw(game) => write!(f, "Game#{} RemoveDraw", game),
+      RequestUndo(game) => write!(f, "Game#{} RequestUndo", game),
+      RemoveUndo(game) => write!(f, "Game#{} RemoveUndo", game),
+      Undo(game) => write!(f, "Game#{} Undo", game),
+      Shout(ref name, ref text) => write!(f, "Shout <{}> {}", name, text),
+      Online(n) => write!(f, "Online {}", n),
+      Message(ref text) => write!(f, "Message {}", text),
+      Error(ref text) => write!(f, "Error {}", text),
+    }
+  }
+}
+
+pub fn parse_client_message(line: &str) -> Result<ClientMessage, ErrorType> {
+  use self::ClientMessage::*;
+  let mut words = Words::new(line);
+  let msg = match words.next()? {
+    "Client" => Client(words.next()?.to_string()),
+    "Login" => {
+      let name = words.next()?;
+      if name == "Guest" && words.is_empty() {
+        LoginGuest
+      } else {
+        Login(name.to_string(), words.next()?.to_string())
+      }
+    },
+    "Seek" => Seek(parse_seek(&mut words)?),
+    "Accept" => Accept(words.number()?),
+    word if word.starts_with("Game#") => {
+      let game = game_number(&words, word)?;
+      let args = words.rest;
+      match words.next()? {
+        "P" | "M" => {
+          words.rest = args;
+          Move(game, words.game_move()?)
+        },
+        "OfferDraw" => OfferDraw(game),
+        "RemoveDraw" => RemoveDraw(game),
+        "RequestUndo" => RequestUndo(game),
+        "RemoveUndo" => RemoveUndo(game),
+        "Resign" => Resign(game),
+        _ => return Err(words.error()),
+      }
+    },
+    "Shout" => Shout(words.text().to_string()),
+    "PING" => Ping,
+    "quit" => Quit,
+    _ => return Err(words.error()),
+  };
+  words.end()?;
+  Ok(msg)
+}
+
+impl fmt::Display for ClientMessage {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use self::ClientMessage::*;
+    match *self {
+      Client(ref name) => write!(f, "Client {}", name),
+      Login(ref name, ref password) => write!(f, "Login {} {}", name, password),
+      LoginGuest => write!(f, "Login Guest"),
+      Seek(ref seek) => {
+        write!(f, "Seek ")?;
+        write_seek(f, seek)
+      },
+      Accept(id) => write!(f, "Accept {}", id),
+      Move(game, ref m) => write!(f, "Game#{} {}", game, to_string(m)),
+      OfferDraw(game) => write!(f, "Game#{} OfferDraw", game),
+      RemoveDraw(game) => write!(f, "Game#{} RemoveDraw", game),
+      RequestUndo(game) => write!(f, "Game#{} RequestUndo", game),
+      RemoveUndo(game) => write!(f, "Game#{} RemoveUndo", game),
+      Resign(game) => write!(f, "Game#{} Resign", game),
+      Shout(ref text) => write!(f, "Shout {}", text),
+      Ping => write!(f, "PING"),
+      Quit => write!(f, "quit"),
+    }
+  }
+}
+
 #[cfg(test)]
 mod tests {
-  use std::path::PathBuf;
-  use game::{self, MoveValidity};
-  use sqlite;
+  use game;
   use super::parse_moves;
-  use super::parse_result;
+  use super::{parse_client_message, parse_server_message, parse_move, to_string, ClientMessage, ServerMessage, Seek};
+  use game::{Komi, Move, Player, Winner};
+  use ptn;
+
+  // What the server sent during a short game as black
+  const SERVER_TRANSCRIPT : &str = "Welcome!\r
+Login or Register\r
+Welcome rustak!\r
+Online 14\r
+Seek new 31 alice 5 600 10 A 4 21 1 0 0\r
+Seek new 32 Guest4 6 900 20 W 0 30 1 1 0\r
+Seek new 33 bob 4 180 5 B\r
+Seek remove 31 alice 5 600 10 A 4 21 1 0 0\r
+Game Start 117 5 alice vs rustak black 600 4 21 1\r
+Game#117 P A1\r
+Game#117 Time 598 600\r
+Game#117 P C3 C\r
+Game#117 M C3 C4 1\r
+Game#117 M A1 A3 1 1\r
+Game#117 OfferDraw\r
+Game#117 RemoveDraw\r
+Game#117 RequestUndo\r
+Game#117 RemoveUndo\r
+Game#117 Undo\r
+Shout <alice> gl  hf\r
+OK\r
+NOK\r
+Game#117 Over 0-R\r
+Game#118 Abandoned. bob quit\r
+Message You have been idle for too long\r
+Error You've been removed from the game\r
+";
 
-  // Games that have the wrong result recorded from when playtak did not properly implement the
-  // dragon rule
-  const PLAYTAK_DRAGON_RULE_BUG_GAMES : [i64; 13] = [3172,4932,6037,6249,14270,15070,15527,16082,16325,17091,17316,17405,17532];
-  // Games that have extra moves past when the game has been won, and the database has the wrong
-  // result recorded
-  const PLAYTAK_INCORRECT_RESULT_GAMES : [i64; 7] = [380, 3018, 9329, 15296, 54675, 81952, 116539];
-  //
-  const PLAYTAK_UNKNOWN_PROBLEM_GAMES: [i64; 3] = [9013,9449,9598];
+  // What a client sent during the same game
+  const CLIENT_TRANSCRIPT : &str = "Client rustak-0.1.0
+Login rustak hunter2
+Login Guest
+Seek 5 600 10 A 4 21 1 0 0
+Seek 6 900 20
+Accept 31
+Game#117 P D4 W
+Game#117 M D4 D2 1 1
+Game#117 OfferDraw
+Game#117 RemoveDraw
+Game#117 RequestUndo
+Game#117 RemoveUndo
+Game#117 Resign
+Shout hello there
+PING
+quit
+";
 
   #[test]
-  fn test_moves() {
-    let mut dbfile = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-    dbfile.push("games_anon.db.1");
-    let connection = sqlite::open(dbfile).unwrap();
-    // Games between sTAKbot1 and sTAKbot2 have been filtered out as it seems a number of them
-    // contain invalid moves
-    let mut cursor = connection.prepare("
-      SELECT size, notation, result, id 
-      FROM games
-      WHERE (player_white != \"sTAKbot1\" or player_black != \"sTAKbot2\")
-        and (player_white != \"sTAKbot2\" or player_black != \"sTAKbot1\")
-    ").unwrap().cursor();
-
-    while let Some(row) = cursor.next().unwrap() {
-      let size = row[0].as_integer().unwrap();
-      let moves_str = row[1].as_string().unwrap();
-      let result_str = row[2].as_string().unwrap();
-      let id = row[3].as_integer().unwrap();
-
-      if result_str.starts_with("0-0") {
-        // Not sure why there exist games with this result.
-        // It's not a valid result
-        // Perhaps these were offered draws?
-        continue;
+  fn server_messages() {
+    use super::ServerMessage::*;
+    let seek = Seek {
+      color: None,
+      komi: Komi::from_half_flats(4),
+      ..Seek::new(5, 600, 10).unwrap()
+    };
+    let expected = vec![
+      Welcome,
+      LoginOrRegister,
+      LoggedIn("rustak".to_string()),
+      Online(14),
+      SeekNew(31, "alice".to_string(), seek),
+      SeekNew(32, "Guest4".to_string(), Seek { color: Some(Player::White), rated: false, ..Seek::new(6, 900, 20).unwrap() }),
+      SeekNew(33, "bob".to_string(), Seek { color: Some(Player::Black), ..Seek::new(4, 180, 5).unwrap() }),
+      SeekRemove(31, "alice".to_string(), seek),
+      GameStart {
+        game: 117, size: 5, white: "alice".to_string(), black: "rustak".to_string(),
+        color: Player::Black, time: 600, komi: Komi::from_half_flats(4), flats: 21, caps: 1,
+      },
+      Move(117, ptn::parse_move("a1").unwrap()),
+      Time(117, 598, 600),
+      Move(117, ptn::parse_move("Cc3").unwrap()),
+      Move(117, ptn::parse_move("c3+").unwrap()),
+      Move(117, ptn::parse_move("2a1+11").unwrap()),
+      OfferDraw(117),
+      RemoveDraw(117),
+      RequestUndo(117),
+      RemoveUndo(117),
+      Undo(117),
+      Shout("alice".to_string(), "gl  hf".to_string()),
+      Ok,
+      Nok,
+      Over(117, Winner::Road(Player::Black)),
+      Abandoned(118),
+      Message("You have been idle for too long".to_string()),
+      Error("You've been removed from the game".to_string()),
+    ];
+    let parsed : Vec<ServerMessage> = SERVER_TRANSCRIPT.lines().map(|line| parse_server_message(line).unwrap()).collect();
+    assert_eq!(parsed, expected);
+
+    // Formatting gives back the line, apart from fields older servers leave out
+    for (line, msg) in SERVER_TRANSCRIPT.lines().zip(parsed.iter()) {
+      let formatted = msg.to_string();
+      assert_eq!(parse_server_message(&formatted).unwrap(), *msg);
+      if !line.starts_with("Seek new 33") && !line.contains("Abandoned") {
+        assert_eq!(formatted, line.trim_end());
       }
-      let mut moves = match parse_moves(moves_str) {
-        Ok((moves, _)) => moves,
-        Err(e) => panic!("Could not parse moves '{}', error: {:?}", moves_str, e),
-      };
-      let result = match parse_result(result_str) {
-        Ok((result, _)) => result,
-        Err(e) => panic!("Could not parse result '{}', error: {:?}", result_str, e),
-      };
-      let mut g = game::new(size as usize).unwrap();
-      for m in moves.iter_mut() {
-        if let Some(_) = g.status() {
-          break;
-        }
-        if let MoveValidity::Valid = g.validate(m) {
-          g.execute(m);
-        } else {
-          panic!("Error during simulated game(id={})\nMove {:?} not valid\nBoard State: \n{}\nMoves str:\n{}", id, m, g.to_string(), moves_str);
-        }
+    }
+
+    for line in ["", "Welcome", "Game#117 P", "Game#117 P A9", "Game#x Undo", "Game#117 Over 2-0", "Online", "Hello"].iter() {
+      assert!(parse_server_message(line).is_err(), "{}", line);
+    }
+
+    let extra = parse_server_message("Game Start 117 5 alice vs rustak black 600 4 21 1 0 1").unwrap();
+    assert_eq!(extra, expected[8]);
+  }
+
+  #[test]
+  fn client_messages() {
+    use super::ClientMessage::*;
+    let expected = vec![
+      Client("rustak-0.1.0".to_string()),
+      Login("rustak".to_string(), "hunter2".to_string()),
+      LoginGuest,
+      Seek(super::Seek { komi: Komi::from_half_flats(4), ..super::Seek::new(5, 600, 10).unwrap() }),
+      Seek(super::Seek::new(6, 900, 20).unwrap()),
+      Accept(31),
+      Move(117, ptn::parse_move("Sd4").unwrap()),
+      Move(117, ptn::parse_move("2d4-11").unwrap()),
+      OfferDraw(117),
+      RemoveDraw(117),
+      RequestUndo(117),
+      RemoveUndo(117),
+      Resign(117),
+      Shout("hello there".to_string()),
+      Ping,
+      Quit,
+    ];
+    let parsed : Vec<ClientMessage> = CLIENT_TRANSCRIPT.lines().map(|line| parse_client_message(line).unwrap()).collect();
+    assert_eq!(parsed, expected);
+    for (line, msg) in CLIENT_TRANSCRIPT.lines().zip(parsed.iter()) {
+      let formatted = msg.to_string();
+      assert_eq!(parse_client_message(&formatted).unwrap(), *msg);
+      if line != "Seek 6 900 20" {
+        assert_eq!(formatted, line);
       }
+    }
+    assert!(parse_client_message("Seek 9 600 10").is_err());
+    assert!(parse_client_message("Accept 31 32").is_err());
+  }
 
-      match result {
-        game::Winner::Other(_) => {},
-        _ => {
-          if PLAYTAK_UNKNOWN_PROBLEM_GAMES.contains(&id) {
-            continue;
-          }
+  #[test]
+  fn format_moves() {
+    assert_eq!(to_string(&ptn::parse_move("c3").unwrap()), "P C3");
+    assert_eq!(to_string(&ptn::parse_move("Sc3").unwrap()), "P C3 W");
+    assert_eq!(to_string(&ptn::parse_move("Ch8").unwrap()), "P H8 C");
+    assert_eq!(to_string(&ptn::parse_move("3a1+12").unwrap()), "M A1 A3 1 2");
+    assert_eq!(to_string(&ptn::parse_move("5e3<1112").unwrap()), "M E3 A3 1 1 1 2");
+    assert_eq!(to_string(&ptn::parse_move("b2-").unwrap()), "M B2 B1 1");
+  }
 
-          if PLAYTAK_INCORRECT_RESULT_GAMES.contains(&id) {
-            continue;
-          }
+  #[test]
+  fn round_trip_moves() {
+    let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+    let mut rand = move |n: usize| {
+      seed ^= seed << 13; seed ^= seed >> 7; seed ^= seed << 17;
+      (seed % n as u64) as usize
+    };
+    for size in 3 .. 9 {
+      // Random games, so there are stacks to move as well as placements
+      for _ in 0 .. 10 {
+        let mut g = game::new(size).unwrap();
+        while g.status().is_none() {
+          let mut moves = Vec::new();
+          g.foreach_move(|m| -> Result<(), ()> {
+            let s = to_string(&m);
+            match parse_move(&s) {
+              Ok((parsed, "")) => assert_eq!(parsed, m, "{}", s),
+              res => panic!("{}: {:?}", s, res),
+            }
+            moves.push(m);
+            Ok(())
+          }).unwrap();
 
-          if PLAYTAK_DRAGON_RULE_BUG_GAMES.contains(&id) {
-            continue;
-          }
-          let simulated = g.status();
-          if simulated.is_none() {
-            if result == game::Winner::Draw { continue; }
-            panic!("Simulated game (id={}) did not terminate, result should have been {:?}\nFinal board state:\n{}", id, result, g.to_string());
-          }
+          let list = moves.iter().map(to_string).collect::<Vec<_>>().join(", ");
+          let (parsed, rest) = parse_moves(&list).unwrap();
+          assert_eq!(parsed, moves);
+          assert_eq!(rest, "");
 
-          if result != simulated.unwrap() {
-            panic!("Simulated game (id={}) had incorrect result {:?}, should have been {:?}\nFinal board state:\n{}", id, simulated, result, g.to_string());
-          }
-        },
+          let m : Move = moves[rand(moves.len())];
+          g.execute(&m);
+        }
       }
     }
   }
 }
-
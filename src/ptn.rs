@@ -1,14 +1,9 @@
-﻿use nom::{self,ErrorKind, digit, IResult, Needed, types::CompleteStr};
-use nom::error_to_list;
-use ::game::{self,Loc,Move,Dir,Piece,Player};
-
-const OUT_OF_RANGE_CHAR_CODE : u32 = 1;
-const TOO_MANY_DROPS_CODE : u32 = 2;
-const NUM_PIECES_MISMATCH_CODE : u32 = 3;
+﻿use ::game::{self,Loc,Move,Dir,Piece,Player,Komi,Rules};
 
 #[derive(Clone)]
 #[derive(Copy)]
 #[derive(Debug)]
+#[derive(PartialEq)]
 pub enum TakAnnotation {
   Tak,
   Tinue,
@@ -17,6 +12,7 @@ pub enum TakAnnotation {
 #[derive(Clone)]
 #[derive(Copy)]
 #[derive(Debug)]
+#[derive(PartialEq)]
 pub enum SubjAnnotation {
   Questionable,
   Surprising,
@@ -28,28 +24,87 @@ pub enum SubjAnnotation {
 
 #[derive(Clone)]
 #[derive(Debug)]
+#[derive(PartialEq)]
 pub struct AnnotatedMove {
   pub m: Move,
   pub annotation: (Option<TakAnnotation>, Option<SubjAnnotation>),
+  // Comments following the move, without the enclosing braces
+  pub comments: Vec<String>,
 }
 
 // TODO: Figure out if we should remove player1 etc tags
 // TODO: Figure out what should be parsed into the struct
 //       and what should be left as tags
-#[derive(Debug)]
+#[derive(Clone,Debug,PartialEq)]
 pub struct Ptn {
   pub player1: String,
   pub player2: String,
   pub size: usize,
+  pub komi: Komi,
   pub result: Option<game::Winner>,
   pub tags: Vec<Tag>,
+  // Comments that come before the first move
+  pub comments: Vec<String>,
   pub moves: Vec<AnnotatedMove>
 }
 
-#[derive(Debug)]
+impl Ptn {
+  pub fn new(size: usize) -> Self {
+    Ptn {
+      player1: String::new(),
+      player2: String::new(),
+      size,
+      komi: Komi::default(),
+      result: None,
+      tags: vec![Tag { name: "size".to_string(), value: size.to_string() }],
+      comments: Vec::new(),
+      moves: Vec::new(),
+    }
+  }
+
+  // Build a PTN from the moves played in a game, i.e. for publishing games played by a bot
+  pub fn from_moves(size: usize, moves: &[Move]) -> Self {
+    let mut ptn = Ptn::new(size);
+    ptn.moves = moves.iter()
+      .map(|m| AnnotatedMove { m: *m, annotation: (None, None), comments: Vec::new() })
+      .collect();
+    ptn
+  }
+
+  // Rules given by the size, komi, flats and caps tags. Returns None if the
+  // flats or caps tags aren't valid numbers.
+  pub fn rules(&self) -> Option<Rules> {
+    let mut rules = Rules { komi: self.komi, ..Rules::new(self.size)? };
+    for tag in &self.tags {
+      match tag.name.as_str() {
+        "flats" => rules.flats = tag.value.trim().parse().ok()?,
+        "caps" => rules.caps = tag.value.trim().parse().ok()?,
+        _ => {},
+      }
+    }
+    Some(rules)
+  }
+
+  // New game with the rules given by the tags
+  pub fn game(&self) -> Option<game::Game> {
+    game::Game::with_rules(self.rules()?)
+  }
+
+  // Position the moves start from: the TPS tag if there is one, otherwise a
+  // new game
+  pub fn start(&self) -> Option<game::Game> {
+    match self.tags.iter().find(|t| t.name == "tps") {
+      Some(tag) => ::tps::parse_with_rules(&tag.value, self.rules()?).ok(),
+      None => self.game(),
+    }
+  }
+}
+
+// Tag names are stored in lowercase
+#[derive(Clone,Debug,PartialEq)]
 pub struct Tag {
-  name: String,
-  value: String,
+  pub name: String,
+  pub value: String,
 }
 
 #[derive(Debug)]
@@ -59,6 +114,8 @@ pub enum ErrorType {
   TooManyDrops,
   InvalidPieceCount,
   NoDrops,
+  InvalidSize,
+  InvalidKomi,
 }
 
 #[derive(Debug)]
@@ -66,22 +123,116 @@ pub struct ParseError {
   err_type: ErrorType
 }
 
+// Error from parsing a whole PTN file. Lines and columns start at 1.
+#[derive(Debug)]
+pub struct PtnError {
+  pub line: usize,
+  pub column: usize,
+  pub err_type: ErrorType,
+}
+
+impl PtnError {
+  fn at(input: &str, remaining: &str, err_type: ErrorType) -> Self {
+    let consumed = &input[.. input.len() - remaining.len()];
+    let line_start = consumed.rfind('\n').map_or(0, |i| i + 1);
+    PtnError {
+      line: consumed.matches('\n').count() + 1,
+      column: consumed[line_start ..].chars().count() + 1,
+      err_type,
+    }
+  }
+}
+
 pub fn parse_move(input: &str) -> Option<Move> {
   match parse_move_internal(input.trim()) {
     Ok(("", m)) => Some(m),
-    Ok((s,_)) => {
-      println!("Not exact match, remaining characters: {}", s);
-      None
-    },
-    Err(e) => {
-      println!("Error: {:?}", e);
-      None
-    }
+    _ => None,
   }
 }
 
-pub fn parse(input: &str) -> Option<Ptn> {
-  None
+pub fn parse(input: &str) -> Result<Ptn, PtnError> {
+  let mut ptn = Ptn {
+    player1: String::new(),
+    player2: String::new(),
+    size: 0,
+    komi: Komi::default(),
+    result: None,
+    tags: Vec::new(),
+    comments: Vec::new(),
+    moves: Vec::new(),
+  };
+
+  let mut remaining = input.trim_start_matches('\u{feff}').trim_start();
+  while remaining.starts_with('[') {
+    let start = remaining;
+    let (rest, tag) = parse_tag(remaining).map_err(|e| PtnError::at(input, start, e.err_type))?;
+    match tag.name.as_str() {
+      "player1" => ptn.player1 = tag.value.clone(),
+      "player2" => ptn.player2 = tag.value.clone(),
+      "size" => match tag.value.trim().parse::<usize>() {
+        Ok(size @ 3 ..= 8) => ptn.size = size,
+        _ => return Err(PtnError::at(input, start, ErrorType::InvalidSize)),
+      },
+      "komi" => match tag.value.trim().parse::<Komi>() {
+        Ok(komi) => ptn.komi = komi,
+        Err(_) => return Err(PtnError::at(input, start, ErrorType::InvalidKomi)),
+      },
+      "result" => if let Ok((_, result)) = parse_winner(tag.value.trim()) {
+        ptn.result = Some(result);
+      },
+      _ => {},
+    }
+    ptn.tags.push(tag);
+    remaining = rest.trim_start();
+  }
+
+  if ptn.size == 0 {
+    return Err(PtnError::at(input, remaining, ErrorType::InvalidSize));
+  }
+
+  let mut finished = false;
+  loop {
+    remaining = remaining.trim_start();
+    if remaining.is_empty() {
+      break;
+    }
+    let start = remaining;
+
+    if remaining.starts_with('{') {
+      let (rest, comment) = parse_comment(remaining).map_err(|e| PtnError::at(input, start, e.err_type))?;
+      match ptn.moves.last_mut() {
+        Some(m) => m.comments.push(comment),
+        None => ptn.comments.push(comment),
+      }
+      remaining = rest;
+      continue;
+    }
+
+    // Nothing but comments may follow the result
+    if finished {
+      let c = remaining.chars().next().unwrap();
+      return Err(PtnError::at(input, start, ErrorType::InvalidChar(c)));
+    }
+
+    if let Some(rest) = parse_move_number(remaining) {
+      remaining = rest;
+    } else if remaining.starts_with("--") && end_of_token(&remaining[2 ..]).is_ok() {
+      // Placeholder for white's move when black moves first
+      remaining = &remaining[2 ..];
+    } else if let Ok((rest, result)) = parse_winner(remaining) {
+      end_of_token(rest).map_err(|e| PtnError::at(input, rest, e.err_type))?;
+      ptn.result = Some(result);
+      finished = true;
+      remaining = rest;
+    } else {
+      let (rest, m) = parse_annotated_move(remaining).map_err(|e| PtnError::at(input, start, e.err_type))?;
+      end_of_token(rest).map_err(|e| PtnError::at(input, rest, e.err_type))?;
+      ptn.moves.push(m);
+      remaining = rest;
+    }
+  }
+
+  Ok(ptn)
 }
 
 macro_rules! parse_char_num (
@@ -134,7 +285,7 @@ fn parse_movement(input: &str) -> Result<(&str,Move),ParseError> {
   let (drops_str, remaining) = remaining.split_at(range);
 
   if range == 0 {
-    Ok((remaining, Move::Move(square, dir, 1, [num_pieces,0,0,0,0,0,0], false)))
+    Ok((remaining, Move::Move(square, dir, 1, [num_pieces,0,0,0,0,0,0])))
   } else if range <= 7 {
     let mut drops = [0u8;7];
     for (i, c) in drops_str.char_indices() {
@@ -142,20 +293,25 @@ fn parse_movement(input: &str) -> Result<(&str,Move),ParseError> {
     }
 
     if num_pieces != drops.iter().sum() {
-      println!("num_pieces={}, drops={:?}", num_pieces, drops);
       return Err(ParseError { err_type: ErrorType::InvalidPieceCount });
     }
 
-    Ok((remaining, Move::Move(square, dir, range as u8, drops, false)))
+    Ok((remaining, Move::Move(square, dir, range as u8, drops)))
   } else {
     Err(ParseError { err_type: ErrorType::TooManyDrops })
   }
 }
 
 fn parse_placement(input: &str) -> Result<(&str,Move),ParseError> {
-  let (remaining, piece) = parse_piece_type(input).unwrap_or((input, Piece::Flat));
-  let (remaining, square) = parse_square(remaining)?;
-  Ok((remaining, Move::Place(square, piece)))
+  // The piece type letters overlap with the file letters (i.e. "c2" or "Cc2"), so only treat
+  // the first letter as a piece type if a square follows it
+  if let Ok((remaining, piece)) = parse_piece_type(input) {
+    if let Ok((remaining, square)) = parse_square(remaining) {
+      return Ok((remaining, Move::Place(square, piece)));
+    }
+  }
+  let (remaining, square) = parse_square(input)?;
+  Ok((remaining, Move::Place(square, Piece::Flat)))
 }
 
 fn parse_move_internal(input: &str) -> Result<(&str,Move),ParseError> {
@@ -182,7 +338,7 @@ fn parse_key(input: &str) -> Result<(&str,String),ParseError> {
   }
 
   let key_len = input.bytes().take_while(|c| match *c {
-    b'a' ..= b'z' | b'A' ..= b'Z' | b'_' => true,
+    b'a' ..= b'z' | b'A' ..= b'Z' | b'0' ..= b'9' | b'_' => true,
     _ => false
   }).count();
 
@@ -204,12 +360,82 @@ fn parse_value(input: &str) -> Result<(&str,String),ParseError> {
 
 fn parse_tag(input: &str) -> Result<(&str,Tag),ParseError> {
   let remaining = expect_char(input, '[')?;
-  let (remaining, name) = parse_key(remaining.trim_left())?;
-  let (remaining, value) = parse_value(remaining.trim_left())?;
-  let remaining = expect_char(remaining.trim_left(), ']')?;
+  let (remaining, name) = parse_key(remaining.trim_start())?;
+  let (remaining, value) = parse_value(remaining.trim_start())?;
+  let remaining = expect_char(remaining.trim_start(), ']')?;
   Ok((remaining, Tag { name, value }))
 }
 
+// Parses a move number, i.e. "12.", returning None if the input doesn't start with one
+fn parse_move_number(input: &str) -> Option<&str> {
+  let digits = input.bytes().take_while(|c| c.is_ascii_digit()).count();
+  if digits > 0 && input[digits ..].starts_with('.') {
+    Some(&input[digits+1 ..])
+  } else {
+    None
+  }
+}
+
+fn parse_comment(input: &str) -> Result<(&str,String),ParseError> {
+  let remaining = expect_char(input, '{')?;
+  match remaining.find('}') {
+    Some(end) => Ok((&remaining[end+1 ..], remaining[.. end].to_string())),
+    None => Err(ParseError { err_type: ErrorType::EndOfFile }),
+  }
+}
+
+fn parse_tak_annotation(input: &str) -> Option<(&str,TakAnnotation)> {
+  if input.starts_with("''") {
+    Some((&input[2..], TakAnnotation::Tinue))
+  } else if input.starts_with('\'') {
+    Some((&input[1..], TakAnnotation::Tak))
+  } else {
+    None
+  }
+}
+
+fn parse_subj_annotation(input: &str) -> Option<(&str,SubjAnnotation)> {
+  let annotations = [
+    ("??", SubjAnnotation::Blunder),
+    ("?!", SubjAnnotation::QuestionableSurprising),
+    ("?", SubjAnnotation::Questionable),
+    ("!!", SubjAnnotation::VerySurprising),
+    ("!?", SubjAnnotation::SurprisingQuestionable),
+    ("!", SubjAnnotation::Surprising),
+  ];
+  annotations.iter()
+    .find(|&&(s, _)| input.starts_with(s))
+    .map(|&(s, a)| (&input[s.len() ..], a))
+}
+
+// A move followed by optional tak and subjective annotations, in either order
+fn parse_annotated_move(input: &str) -> Result<(&str,AnnotatedMove),ParseError> {
+  let (mut remaining, m) = parse_move_internal(input)?;
+  let mut tak = None;
+  let mut subj = None;
+  loop {
+    if let (None, Some((rest, a))) = (tak, parse_tak_annotation(remaining)) {
+      tak = Some(a);
+      remaining = rest;
+    } else if let (None, Some((rest, a))) = (subj, parse_subj_annotation(remaining)) {
+      subj = Some(a);
+      remaining = rest;
+    } else {
+      break;
+    }
+  }
+  Ok((remaining, AnnotatedMove { m, annotation: (tak, subj), comments: Vec::new() }))
+}
+
+// Tokens in the move section must be separated by whitespace or a comment
+fn end_of_token(input: &str) -> Result<(),ParseError> {
+  match input.chars().next() {
+    None | Some('{') => Ok(()),
+    Some(c) if c.is_whitespace() => Ok(()),
+    Some(c) => Err(ParseError { err_type: ErrorType::InvalidChar(c) }),
+  }
+}
+
 fn parse_winner(input: &str) -> Result<(&str, game::Winner), ErrorType> {
   use self::ErrorType::*;
   let mut chars = input.chars();
@@ -299,273 +525,6 @@ fn parse_winner(input: &str) -> Result<(&str, game::Winner), ErrorType> {
   Ok((chars.as_str(), res))
 }
 
-/*
-macro_rules! char_to_number (
-  ($i:expr, $lower:expr, $upper:expr, base: $base:expr) => ({
-    let mut chars = $i.chars();
-    match chars.next() {
-      None             => Err(nom::Err::Incomplete(Needed::Size(1))),
-      Some(c @ $lower ..= $upper)  => Ok((CompleteStr(chars.as_str()), (c as u8) - ($base as u8))),
-      Some(_) => Err(nom::Err::Error(error_position!($i, ErrorKind::Custom(OUT_OF_RANGE_CHAR_CODE)))),
-    }
-  });
-);
-
-// Insert a failure if the given condition evaluates to true
-macro_rules! failure_if (
-  ($i:expr, $cond:expr, $code:expr) => ({
-    cond_with_error!($i, $cond, |_| -> IResult<CompleteStr,()> {
-      Err(nom::Err::Failure(error_position!($i, ErrorKind::Custom($code))))
-    })
-  })
-);
-
-fn eat_ws(input: CompleteStr) -> IResult<CompleteStr, ()> {
-  value!(input, (), take_while!(char::is_whitespace))
-}
-
-// -------------------------------------------
-// ------------- Move Parsing ----------------
-// -------------------------------------------
-
-// Board square parsing (i.e. a1,a2,a3,....,h8)
-// Note: a1 -> Loc { x: 0, y: 0 }, a2 -> Loc { x: 0, y: 1 }, etc.
-named!(pub parse_square(CompleteStr) -> Loc,
-  do_parse!(
-    x: alt!( char_to_number!('a','h', base: 'a')
-           | char_to_number!('A','H', base: 'A')) >>
-    y: char_to_number!('1','8', base: '1') >>
-    (Loc { x, y })
-  )
-);
-
-// PTN format piece type
-named!(parse_piece_type(CompleteStr) -> Piece,
-  alt!( one_of!("fF") => { |_| Piece::Flat }
-      | one_of!("sS") => { |_| Piece::Wall }
-      | one_of!("cC") => { |_| Piece::Cap })
-);
-
-// PTN format movement direction
-named!(parse_direction(CompleteStr) -> Dir,
-  alt!( char!('+') => { |_| Dir::Up }
-      | char!('-') => { |_| Dir::Down }
-      | char!('<') => { |_| Dir::Left }
-      | char!('>') => { |_| Dir::Right })
-);
-
-named!(parse_num_pieces(CompleteStr) -> u8,
-  do_parse!(
-    n: opt!(char_to_number!('1','8', base: '0')) >>
-    (n.unwrap_or(1))
-  )
-);
-
-named!(parse_drops(CompleteStr) -> (u8,[u8;7]),
-  do_parse!(
-    drops: take_while!(|c| c >= '1' && c <= '8') >>
-    failure_if!(drops.len() > 7, TOO_MANY_DROPS_CODE) >>
-    ({
-      let mut d = [0u8; 7];
-      for (i, c) in drops.char_indices() {
-        d[i] = c as u8 - b'0';
-      }
-      (drops.len() as u8, d)
-    })
-  )
-);
-
-// Movement move
-named!(movement(CompleteStr) -> Move,
-  do_parse!(
-    num_pieces: parse_num_pieces >>
-    square: parse_square >>
-    dir: parse_direction >>
-    drops: parse_drops >>
-    // Move is invalid
-    failure_if!(drops.0 > 0 && drops.1.iter().sum::<u8>() != num_pieces, NUM_PIECES_MISMATCH_CODE) >>
-    ({
-      let range = drops.0;
-      let drops = drops.1;
-
-      if range == 0 {
-        // No drops were included, use default (all to adjacent square)
-        Move::Move(square, dir, 1, [num_pieces,0,0,0,0,0,0], false)
-      } else {
-        Move::Move(square, dir, range, drops, false)
-      }
-    })
-  )
-);
-
-// Placement move
-named!(placement(CompleteStr) -> Move,
-  do_parse!(
-    piece: opt!(parse_piece_type) >>
-    square: parse_square >>
-    (Move::Place(square, piece.unwrap_or(Piece::Flat)))
-  )
-);
-
-// Parse a full move
-// Either a placement or a movement
-named!(parse_move_internal(CompleteStr) -> Move,
-  alt!(movement | placement)
-);
-
-// Parsing for move annotations (tak / tinue)
-named!(tak_eval(CompleteStr) -> TakAnnotation,
-  alt!( tag!("''") => { |_| TakAnnotation::Tinue }
-      | tag!("'") => { |_| TakAnnotation::Tak })
-);
-
-// Parsing for move annotations (subjective eval)
-named!(subj_eval(CompleteStr) -> SubjAnnotation,
-  alt!( tag!("??") => { |_| SubjAnnotation::Blunder }
-      | tag!("?!") => { |_| SubjAnnotation::QuestionableSurprising }
-      | tag!("?") => { |_| SubjAnnotation::Questionable }
-      | tag!("!!") => { |_| SubjAnnotation::VerySurprising }
-      | tag!("!?") => { |_| SubjAnnotation::SurprisingQuestionable }
-      | tag!("!") => { |_| SubjAnnotation::Surprising })
-);
-
-named!(annotated_move(CompleteStr) -> AnnotatedMove,
-  do_parse!(
-    parsed_move: parse_move_internal >>
-    annotation: alt!(
-      do_parse!(t: opt!(preceded!(eat_ws, tak_eval)) >>
-                s: opt!(preceded!(eat_ws, subj_eval)) >> (t, s)) |
-      do_parse!(s: opt!(preceded!(eat_ws, subj_eval)) >>
-                t: opt!(preceded!(eat_ws, tak_eval)) >> (t, s))
-    ) >>
-
-    (AnnotatedMove { m: parsed_move, annotation: annotation })
-  )
-);
-
-// -------------------------------------------
-// ------------- Tag Parsing -----------------
-// -------------------------------------------
-
-fn is_tag_char(c: char) -> bool {
-  match c {
-    'a' ..= 'z' | 'A' ..= 'Z' | '0' ..= '9' | '_' => true,
-    _ => false
-  }
-}
-
-named!(tag(CompleteStr) -> Tag,
-  do_parse!(
-    tag!("[") >>
-    eat_ws >>
-    name: take_while!(is_tag_char) >>
-    eat_ws >>
-    value: delimited!(tag!("\""), is_not!("\""), tag!("\"")) >>
-    eat_ws >>
-    tag!("]") >> (
-      Tag {
-        name: name.to_ascii_lowercase(),
-        value: value.to_string()
-      }
-    )
-  )
-);
-
-// -------------------------------------------
-// ------------- Tag Parsing -----------------
-// -------------------------------------------
-
-named!(comment(CompleteStr) -> (), value!((), delimited!(char!('{'),opt!(is_not!("}")),char!('}'))));
-
-named!(result(CompleteStr) -> game::Winner,
-  alt!( tag!("R-0") => { |_| game::Winner::Road(Player::White) }
-      | tag!("0-R") => { |_| game::Winner::Road(Player::Black) }
-      | tag!("F-0") => { |_| game::Winner::Flat(Player::White) }
-      | tag!("0-F") => { |_| game::Winner::Flat(Player::Black) }
-      | tag!("1-0") => { |_| game::Winner::Other(Player::White) }
-      | tag!("0-1") => { |_| game::Winner::Other(Player::Black) }
-      | tag!("1/2-1/2") => { |_| game::Winner::Draw })
-);
-
-fn body(input: CompleteStr) -> IResult<CompleteStr, Vec<AnnotatedMove>> {
-  let mut moves = Vec::new();
-  value!(input, moves, separated_list!(eat_ws,
-    do_parse!(
-      many1!(digit) >> tag!(".") >>
-      many_m_n!(1,2, do_parse!(
-        eat_ws >>
-        opt!(comment) >>
-        eat_ws >>
-        tap!(a_move: annotated_move => moves.push(a_move.clone())) >> ()
-      )) >>
-      opt!(preceded!(eat_ws,comment)) >>
-      ()
-    )
-  ))
-}
-
-pub fn parse_move(input: &str) -> Option<Move> {
-  match exact!(CompleteStr(input.trim()), parse_move_internal) {
-    Ok((_, m)) => Some(m),
-    Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
-      println!("ERROR LIST {:?}", error_to_list(&e));
-      None
-    }
-    Err(nom::Err::Incomplete(n)) => {
-      println!("INCOMPELTE {:?}", n);
-      None
-    },
-  }
-}
-
-pub fn parse(input: &str) -> Option<Ptn> {
-  let result = do_parse!(CompleteStr(input),
-    eat_ws >>
-    tags: separated_list!(eat_ws, tag) >>
-    eat_ws >>
-    moves: body >>
-    eat_ws >>
-    eof!() >>
-    ({
-      let mut notation = Ptn { player1: String::new(), player2: String::new(), size: 0, result: None, tags, moves };
-
-      for tag in &notation.tags {
-        if tag.name == "player1" {
-          notation.player1 = tag.value.clone();
-        }
-        else if tag.name == "player2" {
-          notation.player2 = tag.value.clone();
-        }
-        else if tag.name == "size" {
-          if let Ok(size) = tag.value.parse::<usize>() {
-            notation.size = size;
-          }
-        } else if tag.name == "result" {
-          if let Ok((_,result)) = result(CompleteStr(&tag.value)) {
-            notation.result = Some(result);
-          }
-        }
-      }
-      notation
-    })
-  );
-
-  match result {
-    Ok((_,ptn)) => {
-      if ptn.size < 3 || ptn.size > 8 { None }
-      else { Some(ptn) }
-    }
-    Err(nom::Err::Error(c)) | Err(nom::Err::Failure(c)) => {
-      println!("ERROR LIST {:?}", error_to_list(&c));
-      None
-    }
-    _ => {
-      println!("INCOMPLETE");
-      None
-    }
-  }
-}
-*/
 
 pub fn to_string(m: &Move) -> String {
   match m {
@@ -579,7 +538,7 @@ pub fn to_string(m: &Move) -> String {
       let y = (loc.y + b'1') as char;
       format!("{}{}{}", piecestr, x, y)
     },
-    Move::Move(loc, dir, range, drops, _) => {
+    Move::Move(loc, dir, range, drops) => {
       let piece_count : u8 = drops.iter().sum();
       let x = (loc.x + b'a') as char;
       let y = (loc.y + b'1') as char;
@@ -605,6 +564,226 @@ pub fn to_string(m: &Move) -> String {
   }
 }
 
+fn tag_name(name: &str) -> String {
+  match name {
+    "tps" => "TPS".to_string(),
+    _ => {
+      let mut chars = name.chars();
+      match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+      }
+    }
+  }
+}
+
+fn winner_to_string(winner: game::Winner) -> &'static str {
+  match winner {
+    game::Winner::Road(Player::White) => "R-0",
+    game::Winner::Road(Player::Black) => "0-R",
+    game::Winner::Flat(Player::White) => "F-0",
+    game::Winner::Flat(Player::Black) => "0-F",
+    game::Winner::Other(Player::White) => "1-0",
+    game::Winner::Other(Player::Black) => "0-1",
+    game::Winner::Draw => "1/2-1/2",
+  }
+}
+
+fn annotated_move_to_string(m: &AnnotatedMove) -> String {
+  let mut string = to_string(&m.m);
+  match m.annotation.0 {
+    Some(TakAnnotation::Tak) => string.push('\''),
+    Some(TakAnnotation::Tinue) => string.push_str("''"),
+    None => {},
+  }
+  string.push_str(match m.annotation.1 {
+    Some(SubjAnnotation::Questionable) => "?",
+    Some(SubjAnnotation::Surprising) => "!",
+    Some(SubjAnnotation::Blunder) => "??",
+    Some(SubjAnnotation::VerySurprising) => "!!",
+    Some(SubjAnnotation::QuestionableSurprising) => "?!",
+    Some(SubjAnnotation::SurprisingQuestionable) => "!?",
+    None => "",
+  });
+  for comment in &m.comments {
+    string.push_str(" {");
+    string.push_str(comment);
+    string.push('}');
+  }
+  string
+}
+
+impl ToString for Ptn {
+  fn to_string(&self) -> String {
+    let mut out = String::new();
+    if !self.player1.is_empty() && !self.tags.iter().any(|t| t.name == "player1") {
+      out.push_str(&format!("[Player1 \"{}\"]\n", self.player1));
+    }
+    if !self.player2.is_empty() && !self.tags.iter().any(|t| t.name == "player2") {
+      out.push_str(&format!("[Player2 \"{}\"]\n", self.player2));
+    }
+    if !self.tags.iter().any(|t| t.name == "size") {
+      out.push_str(&format!("[Size \"{}\"]\n", self.size));
+    }
+    for tag in &self.tags {
+      out.push_str(&format!("[{} \"{}\"]\n", tag_name(&tag.name), tag.value));
+    }
+    if self.komi != Komi::default() && !self.tags.iter().any(|t| t.name == "komi") {
+      out.push_str(&format!("[Komi \"{}\"]\n", self.komi));
+    }
+    out.push('\n');
+
+    for comment in &self.comments {
+      out.push_str(&format!("{{{}}}\n", comment));
+    }
+
+    // Number the moves from the TPS tag's move, with a "--" in place of
+    // white's move if black moves first
+    let (round, black_first) = match self.tags.iter().find(|t| t.name == "tps") {
+      Some(tag) => {
+        let fields : Vec<&str> = tag.value.split_whitespace().collect();
+        (fields.get(2).and_then(|r| r.parse().ok()).unwrap_or(1), fields.get(1) == Some(&"2"))
+      },
+      None => (1, false),
+    };
+    let (first, rest) = self.moves.split_at(if black_first { self.moves.len().min(1) } else { 0 });
+    for m in first {
+      out.push_str(&format!("{}. -- {}\n", round, annotated_move_to_string(m)));
+    }
+    let round = round + first.len();
+    for (i, pair) in rest.chunks(2).enumerate() {
+      out.push_str(&format!("{}.", round + i));
+      for m in pair {
+        out.push(' ');
+        out.push_str(&annotated_move_to_string(m));
+      }
+      out.push('\n');
+    }
+
+    if let Some(result) = self.result {
+      out.push_str(winner_to_string(result));
+      out.push('\n');
+    }
+    out
+  }
+}
+
 #[cfg(test)]
 mod test {
+  use super::{parse, ErrorType, Ptn, SubjAnnotation, TakAnnotation};
+  use game::{Dir, Komi, Loc, Move, Piece, Player, Winner};
+
+  const GAME : &str = "[Site \"PlayTak.com\"]
+[Player1 \"alice\"]
+[Player2 \"bob\"]
+[Size \"5\"]
+[Result \"R-0\"]
+
+{Opening comment}
+1. a1 e5 {both corners}
+2. Cc3 d4
+3. c2 b4'
+4. c1 Sd3?!
+5. c3-' {threat} a5
+6. 2c2<11'' {tinue}
+R-0
+";
+
+  #[test]
+  fn parse_game() {
+    let ptn = parse(GAME).unwrap();
+    assert_eq!(ptn.player1, "alice");
+    assert_eq!(ptn.player2, "bob");
+    assert_eq!(ptn.size, 5);
+    assert_eq!(ptn.result, Some(Winner::Road(Player::White)));
+    assert_eq!(ptn.tags.len(), 5);
+    assert_eq!(ptn.tags[1].name, "player1");
+    assert_eq!(ptn.comments, vec!["Opening comment".to_string()]);
+    assert_eq!(ptn.moves.len(), 11);
+
+    assert_eq!(ptn.moves[1].m, Move::Place(Loc { x: 4, y: 4 }, Piece::Flat));
+    assert_eq!(ptn.moves[1].comments, vec!["both corners".to_string()]);
+    assert_eq!(ptn.moves[2].m, Move::Place(Loc { x: 2, y: 2 }, Piece::Cap));
+    assert_eq!(ptn.moves[5].annotation, (Some(TakAnnotation::Tak), None));
+    assert_eq!(ptn.moves[7].annotation, (None, Some(SubjAnnotation::QuestionableSurprising)));
+    assert_eq!(ptn.moves[8].m, Move::Move(Loc { x: 2, y: 2 }, Dir::Down, 1, [1,0,0,0,0,0,0]));
+    assert_eq!(ptn.moves[8].comments, vec!["threat".to_string()]);
+    assert_eq!(ptn.moves[10].m, Move::Move(Loc { x: 2, y: 1 }, Dir::Left, 2, [1,1,0,0,0,0,0]));
+    assert_eq!(ptn.moves[10].annotation, (Some(TakAnnotation::Tinue), None));
+  }
+
+  #[test]
+  fn write_round_trip() {
+    let ptn = parse(GAME).unwrap();
+    let written = ptn.to_string();
+    assert!(written.starts_with("[Site \"PlayTak.com\"]\n[Player1 \"alice\"]\n"));
+    assert_eq!(parse(&written).unwrap(), ptn);
+  }
+
+  #[test]
+  fn write_moves() {
+    let moves = [
+      Move::Place(Loc { x: 0, y: 0 }, Piece::Flat),
+      Move::Place(Loc { x: 4, y: 4 }, Piece::Flat),
+      Move::Place(Loc { x: 2, y: 2 }, Piece::Wall),
+    ];
+    let mut ptn = Ptn::from_moves(5, &moves);
+    ptn.result = Some(Winner::Other(Player::Black));
+    assert_eq!(ptn.to_string(), "[Size \"5\"]\n\n1. a1 e5\n2. Sc3\n0-1\n");
+    assert_eq!(parse(&ptn.to_string()).unwrap(), ptn);
+
+    // Players that aren't in the tags are written too
+    ptn.player1 = "alice".to_string();
+    ptn.player2 = "bob".to_string();
+    assert!(ptn.to_string().starts_with("[Player1 \"alice\"]\n[Player2 \"bob\"]\n[Size \"5\"]\n"));
+  }
+
+  #[test]
+  fn komi() {
+    let ptn = parse("[Size \"6\"]\n[Komi \"2.5\"]\n1. a1 f6").unwrap();
+    assert_eq!(ptn.komi, Komi::from_half_flats(5));
+    assert_eq!(ptn.game().unwrap().komi(), Komi::from_half_flats(5));
+
+    let mut ptn = Ptn::new(6);
+    ptn.komi = Komi::from_half_flats(4);
+    assert_eq!(ptn.to_string(), "[Size \"6\"]\n[Komi \"2\"]\n\n");
+    assert_eq!(parse(&ptn.to_string()).unwrap().komi, ptn.komi);
+  }
+
+  #[test]
+  fn rules() {
+    let ptn = parse("[Size \"6\"]\n[Komi \"2\"]\n[Flats \"30\"]\n[Caps \"2\"]\n1. a1 f6").unwrap();
+    let rules = ptn.rules().unwrap();
+    assert_eq!((rules.size, rules.flats, rules.caps), (6, 30, 2));
+    assert_eq!(rules.komi, Komi::from_half_flats(4));
+    assert_eq!(ptn.game().unwrap().reserves(Player::White).count(Piece::Cap), 2);
+    let ptn = parse("[Size \"5\"]\n[TPS \"x5/x5/x5/x5/2,x3,1 1 2\"]\n\n2. c3\n").unwrap();
+    assert_eq!(::tps::to_string(&ptn.start().unwrap()), "x5/x5/x5/x5/2,x3,1 1 2");
+
+    let ptn = parse("[Size \"5\"]\n[TPS \"x5/x5/x5/x5/2,x3,1 2 4\"]\n\n4. -- c3 5. d3\n").unwrap();
+    assert_eq!(ptn.moves.len(), 2);
+    assert!(ptn.to_string().ends_with("\n\n4. -- c3\n5. d3\n"));
+    assert_eq!(parse(&ptn.to_string()).unwrap(), ptn);
+
+    let ptn = parse("[Size \"6\"]\n[Caps \"two\"]\n").unwrap();
+    assert!(ptn.rules().is_none());
+  }
+
+  #[test]
+  fn error_position() {
+    let err = parse("[Size \"5\"]\n\n1. a1 e5\n2. c3 x9\n").unwrap_err();
+    assert_eq!((err.line, err.column), (4, 7));
+
+    let err = parse("[Size \"5\"]\n1. a1 e5\nR-0 3. c3").unwrap_err();
+    assert_eq!((err.line, err.column), (3, 5));
+
+    let err = parse("[Size \"5\"]\n1. a1 e5?x").unwrap_err();
+    assert_eq!((err.line, err.column), (2, 10));
+
+    let err = parse("[Size \"5\"]\n[Komi \"1.7\"]").unwrap_err();
+    assert_eq!((err.line, err.column), (2, 1));
+
+    let err = parse("1. a1 e5").unwrap_err();
+    match err.err_type { ErrorType::InvalidSize => {}, e => panic!("{:?}", e) }
+  }
 }
@@ -0,0 +1,87 @@
+// Small xorshift generator, so searches can be seeded and reproducible without
+// pulling in another dependency
+#[derive(Debug,Clone)]
+pub struct Rng {
+  state: u64,
+}
+
+impl Rng {
+  pub fn new(seed: u64) -> Self {
+    // xorshift gets stuck at 0
+    Rng { state: if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed } }
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state ^= self.state >> 12;
+    self.state ^= self.state << 25;
+    self.state ^= self.state >> 27;
+    self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+  }
+
+  // Uniform in [0, n)
+  pub fn below(&mut self, n: usize) -> usize {
+    (self.next_u64() % n as u64) as usize
+  }
+
+  // Uniform in [0, 1)
+  pub fn next_f64(&mut self) -> f64 {
+    (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  // Standard normal, by Box-Muller
+  pub fn normal(&mut self) -> f64 {
+    let u = 1.0 - self.next_f64();
+    let v = self.next_f64();
+    (-2.0 * u.ln()).sqrt() * (2.0 * ::std::f64::consts::PI * v).cos()
+  }
+
+  // Gamma(shape, 1), by Marsaglia and Tsang's method
+  pub fn gamma(&mut self, shape: f64) -> f64 {
+    if shape < 1.0 {
+      let u = 1.0 - self.next_f64();
+      return self.gamma(shape + 1.0) * u.powf(1.0 / shape);
+    }
+    let d = shape - 1.0 / 3.0;
+    let c = 1.0 / (9.0 * d).sqrt();
+    loop {
+      let x = self.normal();
+      let v = 1.0 + c * x;
+      if v <= 0.0 { continue; }
+      let v = v * v * v;
+      let u = 1.0 - self.next_f64();
+      if u.ln() < 0.5 * x * x + d - d * v + d * v.ln() {
+        return d * v;
+      }
+    }
+  }
+
+  // Sample from a Dirichlet distribution with n components, all with parameter alpha
+  pub fn dirichlet(&mut self, alpha: f64, n: usize) -> Vec<f64> {
+    let mut sample : Vec<f64> = (0 .. n).map(|_| self.gamma(alpha)).collect();
+    let sum : f64 = sample.iter().sum();
+    if sum > 0.0 {
+      for x in sample.iter_mut() { *x /= sum; }
+    }
+    sample
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::Rng;
+
+  #[test]
+  fn distributions() {
+    let mut rng = Rng::new(7);
+    let n = 20000;
+    for &shape in [0.3, 1.0, 2.5].iter() {
+      let mean = (0 .. n).map(|_| rng.gamma(shape)).sum::<f64>() / n as f64;
+      assert!((mean - shape).abs() < 0.1 * shape.max(1.0), "gamma({}) mean {}", shape, mean);
+    }
+    let sample = rng.dirichlet(0.3, 30);
+    assert_eq!(sample.len(), 30);
+    assert!((sample.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+    assert!(sample.iter().all(|&x| x >= 0.0));
+    assert!((0 .. 1000).all(|_| rng.below(5) < 5));
+  }
+}
@@ -48,7 +48,7 @@ static MOVE_IDXS : [[TableIter; 8]; 9] = [
     TableIter::new(26,30),   TableIter::new(26,31),   TableIter::new(0,0),     TableIter::new(0,0)     ],
 
   [ TableIter::new(0,0),     TableIter::new(57,6),    TableIter::new(57,21),   TableIter::new(57,41),
-    TableIter::new(57,56),   TableIter::new(56,62),   TableIter::new(56,63),   TableIter::new(0,0)     ],
+    TableIter::new(57,56),   TableIter::new(57,62),   TableIter::new(57,63),   TableIter::new(0,0)     ],
 
   [ TableIter::new(0,0),     TableIter::new(120,7),   TableIter::new(120,28),  TableIter::new(120,63),
     TableIter::new(120,98),  TableIter::new(120,119), TableIter::new(120,126), TableIter::new(120,127) ],
@@ -77,9 +77,9 @@ static SMASH_IDXS : [[TableIter; 8]; 9] = [
     TableIter::new(26,29),   TableIter::new(26,31),   TableIter::new(0,0),     TableIter::new(0,0)     ],
 
   [ TableIter::new(0,0),     TableIter::new(57,1),    TableIter::new(57,11),   TableIter::new(57,31),
-    TableIter::new(57,51),   TableIter::new(56,61),   TableIter::new(56,63),   TableIter::new(0,0)     ],
+    TableIter::new(57,51),   TableIter::new(57,61),   TableIter::new(57,63),   TableIter::new(0,0)     ],
 
-  [ TableIter::new(0,0),     TableIter::new(120,1),   TableIter::new(120,13),  TableIter::new(120,49), 
+  [ TableIter::new(0,0),     TableIter::new(120,1),   TableIter::new(120,13),  TableIter::new(120,43), 
     TableIter::new(120,83),  TableIter::new(120,113), TableIter::new(120,125), TableIter::new(120,127) ],
 
   [ TableIter::new(0,0),     TableIter::new(247,1),   TableIter::new(247,15),  TableIter::new(247,57), 
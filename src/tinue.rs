@@ -0,0 +1,320 @@
+use std::cmp::min;
+use std::time::{Duration, Instant};
+use game::{Game, Move, Player, Undo, Winner};
+
+// Proof-number search for tinue, i.e. a forced road win for the attacker. The
+// attacker only plays moves that win or make a road threat, since any other
+// move gives the defender a free move and it wouldn't be tinue anymore.
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum TinueResult {
+  // Winning line for the attacker, with the defender's longest resistance. It
+  // comes from the first proof found, so there may be a quicker win.
+  Tinue(Vec<Move>),
+  NotTinue,
+  // The search ran out of nodes or time
+  Unknown,
+}
+
+#[derive(Debug,Clone,Copy,Default)]
+pub struct Limits {
+  // Maximum number of positions in the search tree
+  pub nodes: Option<usize>,
+  pub time: Option<Duration>,
+}
+
+const INF : u32 = u32::max_value();
+
+struct Node {
+  // Move leading to this position, None for the root
+  m: Option<Move>,
+  parent: usize,
+  children: Vec<usize>,
+  // Whether the attacker is to move
+  attacker: bool,
+  expanded: bool,
+  // Proof and disproof numbers
+  pn: u32,
+  dn: u32,
+  // Road win for the attacker to move, if there is one
+  win: Option<Move>,
+}
+
+impl Node {
+  fn new(m: Option<Move>, parent: usize, attacker: bool) -> Self {
+    Node { m, parent, children: Vec::new(), attacker, expanded: false, pn: 1, dn: 1, win: None }
+  }
+
+  fn prove(&mut self) {
+    self.expanded = true;
+    self.pn = 0;
+    self.dn = INF;
+  }
+
+  fn disprove(&mut self) {
+    self.expanded = true;
+    self.pn = INF;
+    self.dn = 0;
+  }
+}
+
+// Only roads count, so a game that ends on flats is never tinue
+fn road_winner(w: Winner) -> Option<Player> {
+  match w {
+    Winner::Road(p) => Some(p),
+    _ => None,
+  }
+}
+
+struct Solver {
+  game: Game,
+  attacker: Player,
+  nodes: Vec<Node>,
+}
+
+impl Solver {
+  // Add a child of parent for the position after m, which has just been played
+  fn add_child(&mut self, parent: usize, m: Move) {
+    let attacker = !self.nodes[parent].attacker;
+    let mut node = Node::new(Some(m), parent, attacker);
+    match self.game.status() {
+      Some(w) => {
+        if road_winner(w) == Some(self.attacker) { node.prove() } else { node.disprove() }
+      },
+      None => {
+        let player = self.game.cur_player();
+        if self.game.has_road_threat(player) {
+          if attacker {
+            node.win = self.game.road_threats(player).into_iter().next();
+            node.prove();
+          } else {
+            node.disprove();
+          }
+        }
+      },
+    }
+    // Attacker moves that lose or don't threaten anything are left out entirely
+    if !node.attacker && node.pn == INF {
+      return;
+    }
+    let child = self.nodes.len();
+    self.nodes[parent].children.push(child);
+    self.nodes.push(node);
+  }
+
+  fn expand(&mut self, idx: usize) {
+    let moves : Vec<Move> = self.game.moves().collect();
+    self.nodes[idx].expanded = true;
+    if self.nodes[idx].attacker {
+      for m in moves {
+        let undo = self.game.execute(&m);
+        let keep = match self.game.status() {
+          Some(w) => road_winner(w) == Some(self.attacker),
+          None => self.game.has_road_threat(self.attacker),
+        };
+        if keep { self.add_child(idx, m); }
+        self.game.undo(&undo);
+      }
+    } else {
+      for m in moves {
+        let undo = self.game.execute(&m);
+        self.add_child(idx, m);
+        self.game.undo(&undo);
+        // One escape is enough
+        if self.nodes[self.nodes.len() - 1].dn == 0 {
+          break;
+        }
+      }
+    }
+    self.update(idx);
+  }
+
+  // Recompute proof and disproof numbers of an expanded node from its children
+  fn update(&mut self, idx: usize) {
+    let (pn, dn) = {
+      let node = &self.nodes[idx];
+      let children = node.children.iter().map(|&c| &self.nodes[c]);
+      if node.attacker {
+        children.fold((INF, 0u32), |(pn, dn), c| (min(pn, c.pn), dn.saturating_add(c.dn)))
+      } else {
+        children.fold((0u32, INF), |(pn, dn), c| (pn.saturating_add(c.pn), min(dn, c.dn)))
+      }
+    };
+    let node = &mut self.nodes[idx];
+    node.pn = pn;
+    node.dn = dn;
+  }
+
+  fn search(&mut self, limits: &Limits) -> bool {
+    let start = Instant::now();
+    let mut undos : Vec<Undo> = Vec::new();
+    while self.nodes[0].pn != 0 && self.nodes[0].dn != 0 {
+      if limits.nodes.map_or(false, |n| self.nodes.len() > n)
+      || limits.time.map_or(false, |t| start.elapsed() > t) {
+        return false;
+      }
+
+      // Descend to the most proving node
+      let mut idx = 0;
+      while self.nodes[idx].expanded {
+        let node = &self.nodes[idx];
+        let next = if node.attacker {
+          node.children.iter().cloned().find(|&c| self.nodes[c].pn == node.pn)
+        } else {
+          node.children.iter().cloned().find(|&c| self.nodes[c].dn == node.dn)
+        };
+        idx = next.expect("solved node on the most proving path");
+        undos.push(self.game.execute(&self.nodes[idx].m.unwrap()));
+      }
+
+      self.expand(idx);
+      while idx != 0 {
+        idx = self.nodes[idx].parent;
+        self.update(idx);
+      }
+      while let Some(undo) = undos.pop() {
+        self.game.undo(&undo);
+      }
+    }
+    true
+  }
+
+  // Number of plies until the attacker wins from a proven node
+  fn depth(&self, idx: usize) -> u32 {
+    let node = &self.nodes[idx];
+    if node.win.is_some() {
+      return 1;
+    }
+    let depths = node.children.iter().map(|&c| (c, &self.nodes[c]))
+      .filter(|&(_, child)| child.pn == 0)
+      .map(|(c, _)| self.depth(c) + 1);
+    if node.attacker { depths.min().unwrap_or(0) } else { depths.max().unwrap_or(0) }
+  }
+
+  fn pv(&self, mut idx: usize) -> Vec<Move> {
+    let mut line = Vec::new();
+    loop {
+      let node = &self.nodes[idx];
+      if let Some(m) = node.win {
+        line.push(m);
+        return line;
+      }
+      let proven = node.children.iter().cloned().filter(|&c| self.nodes[c].pn == 0);
+      let next = if node.attacker {
+        proven.min_by_key(|&c| self.depth(c))
+      } else {
+        proven.max_by_key(|&c| self.depth(c))
+      };
+      match next {
+        Some(c) => {
+          line.push(self.nodes[c].m.unwrap());
+          idx = c;
+        },
+        None => return line,
+      }
+    }
+  }
+}
+
+// Whether the player to move has tinue
+pub fn solve(game: &Game, limits: Limits) -> TinueResult {
+  solve_for(game, game.cur_player(), limits)
+}
+
+// Whether attacker has tinue. If it's the other player's turn, this checks
+// whether every reply still leaves the attacker with tinue, i.e. whether the
+// attacker's last move was tinue.
+pub fn solve_for(game: &Game, attacker: Player, limits: Limits) -> TinueResult {
+  if let Some(w) = game.status() {
+    return if road_winner(w) == Some(attacker) { TinueResult::Tinue(Vec::new()) } else { TinueResult::NotTinue };
+  }
+
+  let mut solver = Solver {
+    game: game.clone(),
+    attacker,
+    nodes: vec![Node::new(None, 0, game.cur_player() == attacker)],
+  };
+  if !solver.search(&limits) {
+    TinueResult::Unknown
+  } else if solver.nodes[0].pn == 0 {
+    TinueResult::Tinue(solver.pv(0))
+  } else {
+    TinueResult::NotTinue
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{solve, solve_for, Limits, TinueResult};
+  use game::{self, Player, Winner};
+  use ptn;
+  use tps;
+
+  fn replay(position: &str, line: &[&str]) -> game::Game {
+    let mut g = tps::parse(position).unwrap();
+    for m in line {
+      let m = ptn::parse_move(m).unwrap();
+      assert_eq!(g.validate(&m), game::MoveValidity::Valid);
+      g.execute(&m);
+    }
+    g
+  }
+
+  #[test]
+  fn road_in_one() {
+    let g = tps::parse("x5/x5/2,2,x3/x5/1,1,1,1,x 1 5").unwrap();
+    assert_eq!(solve(&g, Limits::default()), TinueResult::Tinue(vec![ptn::parse_move("e1").unwrap()]));
+  }
+
+  #[test]
+  fn double_threat() {
+    // b1 threatens both a1 and b2
+    let position = "2,x4/x2,2,x2/x4,2/1,x4/x2,1,1,1 1 5";
+    let g = tps::parse(position).unwrap();
+    match solve(&g, Limits::default()) {
+      TinueResult::Tinue(line) => {
+        assert_eq!(line.len(), 3);
+        assert_eq!(line[0], ptn::parse_move("b1").unwrap());
+        let mut g = g.clone();
+        for m in line.iter() { g.execute(m); }
+        assert_eq!(g.status(), Some(Winner::Road(Player::White)));
+      },
+      res => panic!("{:?}", res),
+    }
+
+    // After b1 it's black's turn and there's no way out
+    let g = replay(position, &["b1"]);
+    match solve_for(&g, Player::White, Limits::default()) {
+      TinueResult::Tinue(line) => assert_eq!(line.len(), 2),
+      res => panic!("{:?}", res),
+    }
+    // But black being to move doesn't make it tinue for black
+    assert_eq!(solve(&g, Limits::default()), TinueResult::NotTinue);
+  }
+
+  #[test]
+  fn not_tinue() {
+    assert_eq!(solve(&game::new(5).unwrap(), Limits::default()), TinueResult::NotTinue);
+
+    // A single threat is just blocked
+    let g = tps::parse("2,x4/x2,2,x2/x4,2/x5/x2,1,1,1 1 5").unwrap();
+    assert_eq!(solve(&g, Limits::default()), TinueResult::NotTinue);
+
+    // Black threatens a road, so white's threats are too slow
+    let g = tps::parse("2,2,2,2,x/x5/x5/1,x4/x2,1,1,1 1 5").unwrap();
+    assert_eq!(solve(&g, Limits::default()), TinueResult::NotTinue);
+
+    // Filling the board wins on flats, but that isn't a road
+    let position = "2S,1,2S/1,2S,1/1,2,x 1 5";
+    assert_eq!(solve(&tps::parse(position).unwrap(), Limits::default()), TinueResult::NotTinue);
+    let filled = replay(position, &["c1"]);
+    assert_eq!(filled.status(), Some(Winner::Flat(Player::White)));
+    assert_eq!(solve_for(&filled, Player::White, Limits::default()), TinueResult::NotTinue);
+  }
+
+  #[test]
+  fn limits() {
+    let g = tps::parse("2,x4/x2,2,x2/x4,2/1,x4/x2,1,1,1 1 5").unwrap();
+    assert_eq!(solve(&g, Limits { nodes: Some(1), time: None }), TinueResult::Unknown);
+  }
+}
@@ -1 +1,254 @@
-﻿
\ No newline at end of file
+use ::game::{Game, Loc, Piece, Player, Rules};
+use ::bits::{Stack, MAX_HEIGHT};
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum ErrorType {
+  // The board, player and round fields weren't all present
+  MissingField,
+  // Number of rows doesn't give a valid board size
+  InvalidSize(usize),
+  // The row for the given rank doesn't contain exactly `size` squares
+  RowLength(usize),
+  // A square that isn't a valid run of empties or stack, i.e. "12X" or "x0"
+  InvalidSquare(String),
+  InvalidPlayer(String),
+  InvalidRound(String),
+  // More pieces of the given type on the board than the player has in total
+  TooManyPieces(Player, Piece),
+}
+
+enum Square {
+  Empty(usize),
+  Stack(Stack, Piece),
+}
+
+fn parse_square(input: &str) -> Result<Square, ErrorType> {
+  let invalid = || ErrorType::InvalidSquare(input.to_string());
+
+  if input.starts_with('x') {
+    let count = &input[1..];
+    if count.is_empty() {
+      return Ok(Square::Empty(1));
+    }
+    return match count.parse::<usize>() {
+      Ok(n) if n > 0 => Ok(Square::Empty(n)),
+      _ => Err(invalid()),
+    };
+  }
+
+  let (pieces, top) = if input.ends_with('S') {
+    (&input[..input.len()-1], Piece::Wall)
+  } else if input.ends_with('C') {
+    (&input[..input.len()-1], Piece::Cap)
+  } else {
+    (input, Piece::Flat)
+  };
+
+  if pieces.is_empty() {
+    return Err(invalid());
+  }
+
+  let mut stack = Stack::new();
+  for c in pieces.chars() {
+    if stack.len() == MAX_HEIGHT {
+      return Err(invalid());
+    }
+    match c {
+      '1' => stack.push(Player::White),
+      '2' => stack.push(Player::Black),
+      _ => return Err(invalid()),
+    }
+  }
+
+  Ok(Square::Stack(stack, top))
+}
+
+fn parse_row(input: &str, size: usize, rank: usize) -> Result<Vec<(Stack, Piece)>, ErrorType> {
+  let mut row = Vec::with_capacity(size);
+  for square in input.split(',') {
+    match parse_square(square)? {
+      Square::Empty(n) => {
+        for _ in 0 .. n { row.push((Stack::new(), Piece::Flat)); }
+      },
+      Square::Stack(stack, top) => row.push((stack, top)),
+    }
+    if row.len() > size {
+      return Err(ErrorType::RowLength(rank));
+    }
+  }
+
+  if row.len() != size {
+    return Err(ErrorType::RowLength(rank));
+  }
+  Ok(row)
+}
+
+// Parse a position in TPS format, i.e. "x5/x5/x2,12C,x2/x5/2,x4 1 3", using the
+// standard rules for the board size. Reserves are derived from the pieces on the board.
+pub fn parse(input: &str) -> Result<Game, ErrorType> {
+  let size = input.split_whitespace().next().map_or(0, |board| board.split('/').count());
+  match Rules::new(size) {
+    Some(rules) => parse_with_rules(input, rules),
+    None => Err(ErrorType::InvalidSize(size)),
+  }
+}
+
+pub fn parse_with_rules(input: &str, rules: Rules) -> Result<Game, ErrorType> {
+  let mut fields = input.split_whitespace();
+  let (board, player, round) = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+    (Some(b), Some(p), Some(r), None) => (b, p, r),
+    _ => return Err(ErrorType::MissingField),
+  };
+
+  let rows : Vec<&str> = board.split('/').collect();
+  let size = rows.len();
+  if size != rules.size {
+    return Err(ErrorType::InvalidSize(size));
+  }
+  let mut game = match Game::with_rules(rules) {
+    Some(g) => g,
+    None => return Err(ErrorType::InvalidSize(size)),
+  };
+
+  let player = match player {
+    "1" => Player::White,
+    "2" => Player::Black,
+    _ => return Err(ErrorType::InvalidPlayer(player.to_string())),
+  };
+
+  let round = match round.parse::<u32>() {
+    Ok(r) if r > 0 => r,
+    _ => return Err(ErrorType::InvalidRound(round.to_string())),
+  };
+
+  let mut white_reserves = *game.reserves(Player::White);
+  let mut black_reserves = *game.reserves(Player::Black);
+
+  // Rows are listed from the top of the board down
+  for (i, row) in rows.iter().enumerate() {
+    let y = size - 1 - i;
+    for (x, (stack, top)) in parse_row(row, size, y + 1)?.into_iter().enumerate() {
+      for (depth, p) in stack.into_iter().enumerate() {
+        let piece = if depth == 0 { top } else { Piece::Flat };
+        let reserves = match p {
+          Player::White => &mut white_reserves,
+          Player::Black => &mut black_reserves,
+        };
+        if reserves.count(piece) == 0 {
+          return Err(ErrorType::TooManyPieces(p, piece));
+        }
+        reserves.remove(piece);
+      }
+      game.set_square(Loc { x: x as u8, y: y as u8 }, stack, top);
+    }
+  }
+
+  game.set_reserves(Player::White, white_reserves);
+  game.set_reserves(Player::Black, black_reserves);
+  game.set_turn(player, round);
+  Ok(game)
+}
+
+pub fn to_string(game: &Game) -> String {
+  let size = game.size();
+  let mut out = String::new();
+  for j in (0 .. size).rev() {
+    let mut prev_empty = 0;
+    for i in 0 .. size {
+      let loc = Loc { x: i as u8, y: j as u8 };
+      let stack = game.stack(loc);
+      if stack.is_empty() {
+        prev_empty += 1;
+        if i == (size - 1) {
+          out.push('x');
+          if prev_empty > 1 { out.push_str(prev_empty.to_string().as_str()); }
+        }
+      } else {
+        if prev_empty > 0 {
+          out.push('x');
+          if prev_empty > 1 { out.push_str(prev_empty.to_string().as_str()); }
+          out.push(',');
+          prev_empty = 0;
+        }
+        for p in stack.into_iter().rev() {
+          match p {
+            Player::White => out.push('1'),
+            Player::Black => out.push('2'),
+          }
+        }
+        match game.top(loc) {
+          Some(Piece::Cap) => out.push('C'),
+          Some(Piece::Wall) => out.push('S'),
+          _ => {},
+        }
+        if i < (size - 1) {
+          out.push(',');
+        }
+      }
+    }
+    if j > 0 {
+      out.push('/');
+    }
+  }
+
+  out.push(' ');
+  out.push(match game.cur_player() { Player::White => '1', Player::Black => '2' });
+  out.push(' ');
+  out.push_str(game.round().to_string().as_str());
+  out
+}
+
+#[cfg(test)]
+mod test {
+  use super::{parse, to_string, ErrorType};
+  use game::{self, Loc, Move, Piece, Player};
+  use bits::MAX_HEIGHT;
+
+  #[test]
+  fn round_trip() {
+    let positions = [
+      "x3/x3/x3 1 1",
+      "x5/x5/x2,12C,x2/x5/2,x4 1 3",
+      "2,x5/x6/x2,1S,x3/x,221C,x4/x6/1,x4,2 2 6",
+      "x8/x8/x8/x8/x8/x8/x8/x8 2 1",
+    ];
+    for tps in positions.iter() {
+      let g = parse(tps).unwrap();
+      assert_eq!(&to_string(&g), tps);
+    }
+  }
+
+  #[test]
+  fn matches_played_game() {
+    let mut g = game::new(5).unwrap();
+    let moves = [
+      Move::Place(Loc { x: 0, y: 0 }, Piece::Flat),
+      Move::Place(Loc { x: 4, y: 4 }, Piece::Flat),
+      Move::Place(Loc { x: 2, y: 2 }, Piece::Cap),
+      Move::Place(Loc { x: 2, y: 3 }, Piece::Wall),
+    ];
+    for m in moves.iter() {
+      g.execute(m);
+    }
+
+    let parsed = parse(&g.to_string()).unwrap();
+    assert_eq!(parsed.hash(), g.hash());
+    assert_eq!(parsed.reserves(Player::White).count(Piece::Flat), g.reserves(Player::White).count(Piece::Flat));
+    assert_eq!(parsed.reserves(Player::White).count(Piece::Cap), 0);
+    assert_eq!(parsed.reserves(Player::Black).count(Piece::Flat), 19);
+  }
+
+  #[test]
+  fn errors() {
+    assert_eq!(parse("x3/x3 1 1").unwrap_err(), ErrorType::InvalidSize(2));
+    assert_eq!(parse("x3/x3/x3 1").unwrap_err(), ErrorType::MissingField);
+    assert_eq!(parse("x3/x2/x3 1 1").unwrap_err(), ErrorType::RowLength(2));
+    assert_eq!(parse("x3/x3/x,13,x 1 1").unwrap_err(), ErrorType::InvalidSquare("13".to_string()));
+    assert_eq!(parse("x3/x3/x3 3 1").unwrap_err(), ErrorType::InvalidPlayer("3".to_string()));
+    assert_eq!(parse("x3/x3/x3 1 0").unwrap_err(), ErrorType::InvalidRound("0".to_string()));
+    assert_eq!(parse("x3/x3/1C,x2 2 2").unwrap_err(), ErrorType::TooManyPieces(Player::White, Piece::Cap));
+    assert_eq!(parse("x3/x3/11111111111,x2 2 2").unwrap_err(), ErrorType::TooManyPieces(Player::White, Piece::Flat));
+    let tall = "12".repeat(MAX_HEIGHT / 2 + 1);
+    assert_eq!(parse(&format!("x3/x3/{},x2 2 2", tall)).unwrap_err(), ErrorType::InvalidSquare(tall));
+  }
+}